@@ -1,8 +1,12 @@
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
 use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
 use chrono::{Duration, Utc};
-use jsonwebtoken::{DecodingKey, EncodingKey, Header, Validation, decode, encode};
+use jsonwebtoken::{DecodingKey, EncodingKey, Header, Validation, decode, decode_header, encode};
 use password_hash::SaltString;
-use password_hash::rand_core::OsRng;
+use password_hash::rand_core::{OsRng, RngCore};
+use sha2::{Digest, Sha256};
 
 use axum::{
     Json,
@@ -10,14 +14,100 @@ use axum::{
 };
 use uuid::Uuid;
 
-const DEFAULT_SECRET_KEY: &str = "43aaf85b92f1ae6fbcef7732c50a0904";
+/// Published in the repository, only ever used with `--insecure-dev-secret`
+const INSECURE_DEV_SECRET: &str = "43aaf85b92f1ae6fbcef7732c50a0904";
+
+/// Key id of `CHATAPP_JWT_SECRET` when `CHATAPP_JWT_KID` is unset
+const DEFAULT_KID: &str = "default";
+
+static KEYS: OnceLock<JwtKeys> = OnceLock::new();
+
+/// Tokens are signed with the current key and verified with whichever key their
+/// `kid` header names, so old keys keep working while being rotated out.
+struct JwtKeys {
+    kid: String,
+    encoding: EncodingKey,
+    decoding: HashMap<String, DecodingKey>,
+}
+
+/// Access tokens are short-lived, clients get new ones with their refresh token
+pub const ACCESS_TOKEN_LIFETIME: Duration = Duration::minutes(15);
 
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
 pub struct Claims {
     pub sub: Uuid,
+    /// Session the token was issued for, see `sessions`
+    pub sid: Uuid,
     pub exp: usize,
 }
 
+/// Loads the signing key from `CHATAPP_JWT_SECRET` and `CHATAPP_JWT_KID`, and the keys
+/// being rotated out from `CHATAPP_JWT_PREVIOUS_KEYS`, a comma-separated list of
+/// `kid:secret`. Fails when no secret is configured, unless `insecure_dev_secret`.
+pub fn init_keys(insecure_dev_secret: bool) -> Result<(), String> {
+    let secret = match std::env::var("CHATAPP_JWT_SECRET") {
+        Ok(secret) if secret.is_empty() => {
+            return Err("CHATAPP_JWT_SECRET is empty".into());
+        }
+        Ok(secret) if secret == INSECURE_DEV_SECRET && !insecure_dev_secret => {
+            return Err(
+                "CHATAPP_JWT_SECRET is the published development secret, choose another one".into(),
+            );
+        }
+        Ok(secret) => secret,
+        Err(_) if insecure_dev_secret => {
+            tracing::warn!(
+                "signing tokens with the published development secret, anyone can forge them"
+            );
+            INSECURE_DEV_SECRET.to_string()
+        }
+        Err(_) => {
+            return Err(
+                "CHATAPP_JWT_SECRET is not set, pass --insecure-dev-secret to run without one"
+                    .into(),
+            );
+        }
+    };
+
+    let kid = std::env::var("CHATAPP_JWT_KID").unwrap_or_else(|_| DEFAULT_KID.to_string());
+
+    let mut decoding = HashMap::from([(kid.clone(), DecodingKey::from_secret(secret.as_ref()))]);
+
+    if let Ok(previous) = std::env::var("CHATAPP_JWT_PREVIOUS_KEYS") {
+        for entry in previous.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (previous_kid, previous_secret) = entry
+                .split_once(':')
+                .filter(|(k, s)| !k.is_empty() && !s.is_empty())
+                .ok_or("CHATAPP_JWT_PREVIOUS_KEYS entries must look like kid:secret")?;
+
+            if decoding.contains_key(previous_kid) {
+                return Err(format!("Key id {previous_kid} is used more than once"));
+            }
+
+            decoding.insert(
+                previous_kid.to_string(),
+                DecodingKey::from_secret(previous_secret.as_ref()),
+            );
+        }
+    }
+
+    tracing::info!(
+        "signing tokens with key {kid}, accepting {} key(s)",
+        decoding.len()
+    );
+
+    KEYS.set(JwtKeys {
+        encoding: EncodingKey::from_secret(secret.as_ref()),
+        kid,
+        decoding,
+    })
+    .map_err(|_| "JWT keys are already loaded".to_string())
+}
+
+fn keys() -> &'static JwtKeys {
+    KEYS.get().expect("JWT keys are loaded at startup")
+}
+
 pub fn hash_password(password: &str) -> Result<String, String> {
     let salt = SaltString::generate(OsRng);
     let argon2 = Argon2::default();
@@ -38,26 +128,41 @@ pub fn verify_password(hash: &str, password: &str) -> bool {
     }
 }
 
-pub fn create_jwt(user_uuid: Uuid) -> Result<String, String> {
+/// Opaque token for refresh tokens and login challenges
+pub fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    hex(&bytes)
+}
+
+/// Random tokens are only stored hashed, they are random enough not to need a salt
+pub fn hash_token(token: &str) -> String {
+    hex(&Sha256::digest(token.as_bytes()))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+pub fn create_jwt(user_uuid: Uuid, session: Uuid) -> Result<String, String> {
     let expiration = Utc::now()
-        .checked_add_signed(Duration::days(100))
+        .checked_add_signed(ACCESS_TOKEN_LIFETIME)
         .expect("valid timestamp")
         .timestamp();
 
     let claims = Claims {
         sub: user_uuid,
+        sid: session,
         exp: expiration as usize,
     };
 
-    let secret =
-        std::env::var("CHATAPP_JWT_SECRET").unwrap_or_else(|_| DEFAULT_SECRET_KEY.to_string());
+    let keys = keys();
+    let header = Header {
+        kid: Some(keys.kid.clone()),
+        ..Header::default()
+    };
 
-    encode(
-        &Header::default(),
-        &claims,
-        &EncodingKey::from_secret(secret.as_ref()),
-    )
-    .map_err(|_| "Token creation failed".into())
+    encode(&header, &claims, &keys.encoding).map_err(|_| "Token creation failed".into())
 }
 
 pub fn verify_jwt(headers: HeaderMap) -> Result<Claims, (StatusCode, String)> {
@@ -67,16 +172,21 @@ pub fn verify_jwt(headers: HeaderMap) -> Result<Claims, (StatusCode, String)> {
         .and_then(|s| s.strip_prefix("Bearer "))
         .ok_or((StatusCode::UNAUTHORIZED, "Missing token".to_string()))?;
 
-    let secret =
-        std::env::var("CHATAPP_JWT_SECRET").unwrap_or_else(|_| DEFAULT_SECRET_KEY.to_string());
+    decode_jwt(token)
+}
+
+pub fn decode_jwt(token: &str) -> Result<Claims, (StatusCode, String)> {
+    let invalid = || (StatusCode::UNAUTHORIZED, "Invalid token".to_string());
+
+    // Tokens signed with a key that was rotated out are rejected here
+    let kid = decode_header(token).map_err(|_| invalid())?.kid;
+    let key = kid
+        .and_then(|kid| keys().decoding.get(&kid))
+        .ok_or_else(invalid)?;
 
-    decode::<Claims>(
-        token,
-        &DecodingKey::from_secret(secret.as_ref()),
-        &Validation::default(),
-    )
-    .map(|data| data.claims)
-    .map_err(|_| (StatusCode::UNAUTHORIZED, "Invalid token".to_string()))
+    decode::<Claims>(token, key, &Validation::default())
+        .map(|data| data.claims)
+        .map_err(|_| invalid())
 }
 
 pub async fn validate_token(
@@ -85,3 +195,25 @@ pub async fn validate_token(
     let _ = verify_jwt(headers)?;
     Ok(Json(serde_json::json!({"valid": true})))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generated_tokens_are_random_hex() {
+        let token = generate_token();
+
+        assert_eq!(token.len(), 64);
+        assert!(token.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_ne!(token, generate_token());
+    }
+
+    #[test]
+    fn tokens_are_hashed_with_sha256() {
+        assert_eq!(
+            hash_token("abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+}
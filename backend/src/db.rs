@@ -15,6 +15,16 @@ pub async fn user_id_from_uuid(db: &PgPool, user_uuid: Uuid) -> Result<i32, (Sta
         .map_err(|_| (StatusCode::UNAUTHORIZED, String::from("Wrong token")))
 }
 
+/// Looks up someone other than the caller, who may not exist.
+pub async fn find_user_by_uuid(db: &PgPool, user_uuid: Uuid) -> Result<i32, (StatusCode, String)> {
+    sqlx::query_scalar("SELECT id FROM user_ WHERE uuid = $1")
+        .bind(user_uuid)
+        .fetch_optional(db)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "DB error".into()))?
+        .ok_or((StatusCode::NOT_FOUND, "User not found".into()))
+}
+
 pub async fn room_id_from_uuid(db: &PgPool, room_uuid: Uuid) -> Result<i32, (StatusCode, String)> {
     sqlx::query_scalar("SELECT id FROM room_ WHERE uuid = $1")
         .bind(room_uuid)
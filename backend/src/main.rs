@@ -21,6 +21,9 @@ mod auth;
 mod db;
 mod realtime;
 mod routes;
+mod sessions;
+mod storage;
+mod totp;
 
 #[derive(clap::Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -33,6 +36,31 @@ pub struct Cli {
     #[arg(short, long, default_value = "localhost:5432")]
     database: String,
 
+    /// Realtime backend, use `postgres` when running several instances
+    #[arg(long, value_enum, default_value = "memory")]
+    realtime: realtime::BackendKind,
+
+    /// Directory where uploaded attachments are stored
+    #[arg(long, default_value = "uploads")]
+    uploads_dir: std::path::PathBuf,
+
+    /// Largest accepted attachment, in bytes
+    #[arg(long, default_value_t = 10 * 1024 * 1024)]
+    max_upload_size: usize,
+
+    /// Accepted attachment MIME types, `image/*` accepts every image type
+    #[arg(
+        long,
+        value_delimiter = ',',
+        default_value = "image/png,image/jpeg,image/gif,image/webp,application/pdf,text/plain"
+    )]
+    upload_types: Vec<String>,
+
+    /// Sign tokens with the development secret published in the repository when
+    /// `CHATAPP_JWT_SECRET` is unset. Never use this in production
+    #[arg(long)]
+    insecure_dev_secret: bool,
+
     /// Verbose mode
     #[arg(short, long)]
     verbose: bool,
@@ -46,12 +74,20 @@ async fn main() -> anyhow::Result<()> {
         .with_max_level(tracing::Level::INFO)
         .init();
 
+    auth::init_keys(cli.insecure_dev_secret).map_err(anyhow::Error::msg)?;
+
     tracing::info!("Connecting to database...");
     let db_pool = db::init_db(cli.database).await?;
 
     let cors = CorsLayer::new()
         .allow_origin(Any)
-        .allow_methods([Method::GET, Method::POST])
+        .allow_methods([
+            Method::GET,
+            Method::POST,
+            Method::PUT,
+            Method::PATCH,
+            Method::DELETE,
+        ])
         .allow_headers([header::AUTHORIZATION, header::CONTENT_TYPE]);
 
     let governor_conf = GovernorConfigBuilder::default()
@@ -72,15 +108,47 @@ async fn main() -> anyhow::Result<()> {
         }
     });
 
-    let realtime = realtime::Realtime::new();
+    let realtime = match cli.realtime {
+        realtime::BackendKind::Memory => realtime::Realtime::new(),
+        realtime::BackendKind::Postgres => {
+            realtime::Realtime::with_backend(realtime::PostgresBackend::new(db_pool.clone()))
+        }
+    };
+
+    tokio::spawn(routes::presence::expire_stale_ws_sessions(
+        db_pool.clone(),
+        realtime.clone(),
+    ));
+
+    let storage =
+        storage::Storage::new(cli.uploads_dir, cli.max_upload_size, cli.upload_types).await?;
+
+    tokio::spawn(routes::attachments::sweep_unsent_attachments(
+        db_pool.clone(),
+        storage.clone(),
+    ));
 
     let mut app = Router::new()
         .merge(routes::users::routes())
+        .merge(routes::sessions::routes())
+        .merge(routes::totp::routes())
         .merge(routes::rooms::routes())
+        .merge(routes::roles::routes())
+        .merge(routes::room_settings::routes())
+        .merge(routes::invite_links::routes())
+        .merge(routes::directory::routes())
         .merge(routes::messages::routes())
+        .merge(routes::search::routes())
+        .merge(routes::mentions::routes())
+        .merge(routes::reactions::routes())
+        .merge(routes::receipts::routes())
         .merge(routes::friends::routes())
+        .merge(routes::presence::routes())
+        .merge(routes::attachments::routes())
         .merge(routes::ws::routes())
+        .layer(middleware::from_fn(sessions::require_live_session))
         .layer(Extension(db_pool))
+        .layer(Extension(storage))
         .layer(Extension(realtime))
         .layer(GovernorLayer::new(governor_conf))
         .layer(cors);
@@ -119,7 +187,7 @@ async fn log_json_body(req: Request, next: Next) -> Response {
         .headers
         .get(CONTENT_TYPE)
         .and_then(|v| v.to_str().ok())
-        .map_or(false, |v| v.contains("application/json"));
+        .is_some_and(|v| v.contains("application/json"));
 
     let bytes = if is_json {
         // Read the body bytes
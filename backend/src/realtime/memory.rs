@@ -0,0 +1,55 @@
+use dashmap::DashMap;
+use std::sync::Arc;
+use tokio::sync::broadcast;
+
+use super::{Event, RealtimeBackend, Topic};
+
+/// In-process broadcast channels, one per topic with subscribers.
+#[derive(Clone)]
+pub struct MemoryBackend {
+    pub topics: Arc<DashMap<Topic, broadcast::Sender<Event>>>,
+}
+
+impl MemoryBackend {
+    pub fn new() -> Self {
+        Self {
+            topics: Arc::new(DashMap::new()),
+        }
+    }
+
+    /// Tells every subscriber that events may have been lost, dropping the
+    /// topics nobody listens to anymore.
+    pub fn signal_gap(&self) {
+        self.topics
+            .retain(|_, sender| sender.send(Event::Gap).is_ok());
+    }
+}
+
+impl RealtimeBackend for MemoryBackend {
+    fn publish(&self, topic: Topic, event: Event) {
+        let delivered = match self.topics.get(&topic) {
+            Some(sender) => sender.send(event).is_ok(),
+            None => return,
+        };
+
+        // Every receiver was dropped since the last event
+        if !delivered {
+            self.topics
+                .remove_if(&topic, |_, sender| sender.receiver_count() == 0);
+        }
+    }
+
+    fn subscribe(&self, topic: Topic) -> broadcast::Receiver<Event> {
+        if let Some(sender) = self.topics.get(&topic) {
+            return sender.subscribe();
+        }
+
+        // Topics left without receivers and never published to again would pile up
+        self.topics.retain(|_, sender| sender.receiver_count() > 0);
+
+        self.topics
+            .entry(topic)
+            .or_insert_with(|| broadcast::channel(100).0)
+            .subscribe()
+    }
+}
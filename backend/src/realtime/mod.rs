@@ -0,0 +1,157 @@
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokio::sync::broadcast;
+use uuid::Uuid;
+
+use crate::routes::{mentions::Mention, messages::Message, presence::Presence, roles::Role};
+
+mod memory;
+mod postgres;
+
+pub use memory::MemoryBackend;
+pub use postgres::PostgresBackend;
+
+pub type RoomId = i32;
+pub type UserId = i32;
+
+/// What subscribers listen to: everything happening in a room,
+/// or events addressed to one user wherever they are.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Topic {
+    Room(RoomId),
+    User(UserId),
+}
+
+/// Everything that can happen in a room, as pushed to its subscribers.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    Message(Box<Message>),
+    MessageEdited {
+        room: Uuid,
+        uuid: Uuid,
+        content: String,
+        edited_at: String,
+    },
+    MessageDeleted {
+        room: Uuid,
+        uuid: Uuid,
+        deleted_at: String,
+    },
+    /// `uuid` is the message reacted to, `user` the reactor's username
+    ReactionAdded {
+        room: Uuid,
+        uuid: Uuid,
+        emoji: String,
+        user: String,
+    },
+    ReactionRemoved {
+        room: Uuid,
+        uuid: Uuid,
+        emoji: String,
+        user: String,
+    },
+    MemberJoined {
+        room: Uuid,
+        user: String,
+    },
+    /// `user` left the room or was kicked out of it, their feed of the room ends here.
+    /// `user_uuid` identifies them even if they were renamed since connecting
+    MemberLeft {
+        room: Uuid,
+        user: String,
+        user_uuid: Uuid,
+        kicked: bool,
+    },
+    /// New settings of a room, also announced by a system message
+    RoomUpdated {
+        room: Uuid,
+        name: String,
+        description: String,
+        topic: String,
+        avatar: Option<Uuid>,
+        public: bool,
+    },
+    /// The room is gone along with its messages, every feed of it ends here
+    RoomDeleted {
+        room: Uuid,
+    },
+    RoleChanged {
+        room: Uuid,
+        user: String,
+        role: Role,
+    },
+    /// Never stored, `typing: false` is also sent when the state expires
+    Typing {
+        room: Uuid,
+        user: String,
+        user_uuid: Uuid,
+        typing: bool,
+    },
+    /// Sent to the user's friends and to the user's own sockets
+    Presence {
+        uuid: Uuid,
+        user: String,
+        presence: Presence,
+    },
+    /// Sent to a mentioned user only, whether or not they follow the room
+    Mentioned(Box<Mention>),
+    /// `user` has read everything up to the message `uuid`
+    MessagesRead {
+        room: Uuid,
+        user: String,
+        uuid: Uuid,
+        read_at: String,
+    },
+    /// Never published, events may have been lost since the previous one:
+    /// room feeds replay what they missed, user sockets get it as is
+    Gap,
+}
+
+/// Fans out events to every subscriber of a topic.
+pub trait RealtimeBackend: Send + Sync {
+    fn publish(&self, topic: Topic, event: Event);
+    fn subscribe(&self, topic: Topic) -> broadcast::Receiver<Event>;
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum BackendKind {
+    /// Single instance, in-process channels
+    Memory,
+    /// Relays events between instances through Postgres LISTEN/NOTIFY
+    Postgres,
+}
+
+#[derive(Clone)]
+pub struct Realtime {
+    backend: Arc<dyn RealtimeBackend>,
+}
+
+impl Realtime {
+    pub fn new() -> Self {
+        Self::with_backend(MemoryBackend::new())
+    }
+
+    pub fn with_backend(backend: impl RealtimeBackend + 'static) -> Self {
+        Self {
+            backend: Arc::new(backend),
+        }
+    }
+
+    pub fn publish(&self, room: RoomId, event: Event) {
+        self.backend.publish(Topic::Room(room), event);
+    }
+
+    pub fn subscribe(&self, room: RoomId) -> broadcast::Receiver<Event> {
+        self.backend.subscribe(Topic::Room(room))
+    }
+
+    pub fn publish_to_user(&self, user: UserId, event: Event) {
+        self.backend.publish(Topic::User(user), event);
+    }
+
+    pub fn subscribe_user(&self, user: UserId) -> broadcast::Receiver<Event> {
+        self.backend.subscribe(Topic::User(user))
+    }
+}
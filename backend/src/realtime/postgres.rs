@@ -0,0 +1,210 @@
+use serde::{Deserialize, Serialize};
+use sqlx::{PgPool, postgres::PgListener};
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc};
+use uuid::Uuid;
+
+use super::{Event, MemoryBackend, RealtimeBackend, Topic};
+
+const CHANNEL: &str = "chatapp_realtime";
+
+/// Postgres rejects NOTIFY payloads of 8000 bytes or more
+const MAX_PAYLOAD: usize = 7999;
+
+/// How long larger events are kept in `realtime_event_` for the other instances
+const STORED_EVENT_LIFETIME_SECS: i32 = 60;
+
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+#[derive(Serialize, Deserialize)]
+struct Envelope {
+    /// Instance that published the event and already delivered it locally
+    origin: Uuid,
+    topic: Topic,
+    event: Event,
+}
+
+/// What goes through `NOTIFY`: the event itself, or where to find it when too large
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum Notification {
+    Inline(Envelope),
+    Stored { origin: Uuid, stored: i64 },
+}
+
+/// Serialized envelope waiting to be relayed
+enum Outgoing {
+    Inline(String),
+    Stored(String),
+}
+
+/// Delivers events to local subscribers right away and relays them to the
+/// other instances with `NOTIFY`. A dedicated `LISTEN` connection feeds their
+/// events back into the local channels.
+pub struct PostgresBackend {
+    local: MemoryBackend,
+    origin: Uuid,
+    outgoing: mpsc::UnboundedSender<Outgoing>,
+}
+
+impl PostgresBackend {
+    pub fn new(db: PgPool) -> Self {
+        let local = MemoryBackend::new();
+        let origin = Uuid::now_v7();
+        let (outgoing, queue) = mpsc::unbounded_channel();
+
+        tokio::spawn(notify(db.clone(), queue, origin));
+        tokio::spawn(listen(db, local.clone(), origin));
+
+        Self {
+            local,
+            origin,
+            outgoing,
+        }
+    }
+}
+
+impl RealtimeBackend for PostgresBackend {
+    fn publish(&self, topic: Topic, event: Event) {
+        let envelope = Envelope {
+            origin: self.origin,
+            topic,
+            event: event.clone(),
+        };
+        let payload = serde_json::to_string(&Notification::Inline(envelope)).unwrap();
+
+        // Messages can be several times larger than a NOTIFY payload
+        let outgoing = if payload.len() > MAX_PAYLOAD {
+            Outgoing::Stored(payload)
+        } else {
+            Outgoing::Inline(payload)
+        };
+
+        self.local.publish(topic, event);
+        let _ = self.outgoing.send(outgoing);
+    }
+
+    fn subscribe(&self, topic: Topic) -> broadcast::Receiver<Event> {
+        self.local.subscribe(topic)
+    }
+}
+
+/// Sends queued payloads one at a time, so other instances get them in order.
+async fn notify(db: PgPool, mut queue: mpsc::UnboundedReceiver<Outgoing>, origin: Uuid) {
+    while let Some(outgoing) = queue.recv().await {
+        let payload = match outgoing {
+            Outgoing::Inline(payload) => payload,
+            Outgoing::Stored(payload) => match store_event(&db, &payload).await {
+                Ok(stored) => {
+                    serde_json::to_string(&Notification::Stored { origin, stored }).unwrap()
+                }
+                Err(e) => {
+                    tracing::error!("failed to store large realtime event: {e}");
+                    continue;
+                }
+            },
+        };
+
+        if let Err(e) = sqlx::query("SELECT pg_notify($1, $2)")
+            .bind(CHANNEL)
+            .bind(&payload)
+            .execute(&db)
+            .await
+        {
+            tracing::error!("failed to relay realtime event: {e}");
+        }
+    }
+}
+
+/// Keeps an event too large for `NOTIFY` until the other instances fetched it,
+/// clearing out the ones old enough to have been fetched already.
+async fn store_event(db: &PgPool, payload: &str) -> Result<i64, sqlx::Error> {
+    sqlx::query("DELETE FROM realtime_event_ WHERE created_at < now() - make_interval(secs => $1)")
+        .bind(STORED_EVENT_LIFETIME_SECS)
+        .execute(db)
+        .await?;
+
+    sqlx::query_scalar("INSERT INTO realtime_event_ (payload) VALUES ($1) RETURNING id")
+        .bind(payload)
+        .fetch_one(db)
+        .await
+}
+
+async fn fetch_stored_event(db: &PgPool, stored: i64) -> Option<Envelope> {
+    let payload: Option<String> =
+        sqlx::query_scalar("SELECT payload FROM realtime_event_ WHERE id = $1")
+            .bind(stored)
+            .fetch_optional(db)
+            .await
+            .inspect_err(|e| tracing::error!("failed to fetch stored realtime event: {e}"))
+            .ok()
+            .flatten();
+
+    match serde_json::from_str::<Notification>(&payload?) {
+        Ok(Notification::Inline(envelope)) => Some(envelope),
+        _ => {
+            tracing::warn!("ignoring malformed stored realtime event {stored}");
+            None
+        }
+    }
+}
+
+async fn listen(db: PgPool, local: MemoryBackend, origin: Uuid) {
+    let mut reconnecting = false;
+
+    loop {
+        let mut listener = match PgListener::connect_with(&db).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                tracing::error!("realtime listener could not connect: {e}");
+                tokio::time::sleep(RETRY_DELAY).await;
+                continue;
+            }
+        };
+
+        if let Err(e) = listener.listen(CHANNEL).await {
+            tracing::error!("realtime listener could not LISTEN: {e}");
+            tokio::time::sleep(RETRY_DELAY).await;
+            continue;
+        }
+
+        // Whatever was sent while disconnected never reached this instance
+        if reconnecting {
+            local.signal_gap();
+        }
+        reconnecting = true;
+
+        loop {
+            let notification = match listener.try_recv().await {
+                Ok(Some(notification)) => notification,
+                Ok(None) => {
+                    tracing::warn!("realtime listener lost its connection");
+                    break;
+                }
+                Err(e) => {
+                    tracing::error!("realtime listener disconnected: {e}");
+                    break;
+                }
+            };
+
+            let envelope = match serde_json::from_str::<Notification>(notification.payload()) {
+                Ok(Notification::Inline(envelope)) if envelope.origin != origin => Some(envelope),
+                Ok(Notification::Stored {
+                    origin: from,
+                    stored,
+                }) if from != origin => fetch_stored_event(&db, stored).await,
+                Ok(_) => None,
+                Err(e) => {
+                    tracing::warn!("ignoring malformed realtime event: {e}");
+                    None
+                }
+            };
+
+            if let Some(envelope) = envelope {
+                local.publish(envelope.topic, envelope.event);
+            }
+        }
+
+        tokio::time::sleep(RETRY_DELAY).await;
+    }
+}
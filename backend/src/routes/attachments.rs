@@ -0,0 +1,371 @@
+use axum::{
+    Extension, Json, Router,
+    extract::{DefaultBodyLimit, Multipart, Path},
+    http::{HeaderMap, HeaderValue, StatusCode, header},
+    response::IntoResponse,
+    routing::{get, post},
+};
+use sqlx::PgPool;
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::{
+    auth::verify_jwt,
+    db::{room_id_from_uuid, user_id_from_uuid},
+    routes::roles::{Permission, check_permission},
+    storage::{self, Storage},
+};
+
+const MAX_FILENAME_LENGTH: usize = 255;
+
+/// How long an upload may wait for the message showing it
+const UNSENT_ATTACHMENT_LIFETIME_SECS: i32 = 60 * 60;
+const SWEEP_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// Metadata of an uploaded file, also embedded in attachment messages
+#[derive(sqlx::FromRow, serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct Attachment {
+    pub uuid: Uuid,
+    pub filename: String,
+    pub mime_type: String,
+    pub size: i64,
+    /// Whether `/thumbnail` can be fetched for it
+    pub thumbnail: bool,
+}
+
+pub fn routes() -> Router {
+    Router::new()
+        .route(
+            "/attachments/{room_uuid}",
+            // The size limit is enforced while streaming the file
+            post(upload_attachment).layer(DefaultBodyLimit::disable()),
+        )
+        .route(
+            "/attachments/{room_uuid}/{attachment_uuid}",
+            get(download_attachment),
+        )
+        .route(
+            "/attachments/{room_uuid}/{attachment_uuid}/thumbnail",
+            get(download_thumbnail),
+        )
+}
+
+/// Keeps the last path component and drops characters that would break headers.
+fn sanitize_filename(name: &str) -> String {
+    let name: String = name
+        .rsplit(['/', '\\'])
+        .next()
+        .unwrap_or_default()
+        .chars()
+        .filter(|c| !c.is_control() && *c != '"')
+        .take(MAX_FILENAME_LENGTH)
+        .collect();
+
+    match name.trim() {
+        "" | "." | ".." => String::from("file"),
+        name => name.to_string(),
+    }
+}
+
+/// Plain ASCII `filename` for old clients, the exact name in `filename*`.
+fn content_disposition(disposition: &str, filename: &str) -> String {
+    let ascii: String = filename
+        .chars()
+        .map(|c| if c.is_ascii() { c } else { '_' })
+        .collect();
+
+    let encoded: String = filename
+        .bytes()
+        .map(|b| match b {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{b:02X}"),
+        })
+        .collect();
+
+    format!("{disposition}; filename=\"{ascii}\"; filename*=UTF-8''{encoded}")
+}
+
+/// Resolves the caller and checks what they can do in the room.
+async fn member_of(
+    db: &PgPool,
+    headers: HeaderMap,
+    room_uuid: Uuid,
+    permission: Permission,
+) -> Result<(i32, i32), (StatusCode, String)> {
+    let claims = verify_jwt(headers)?;
+
+    let user_id = user_id_from_uuid(db, claims.sub).await?;
+    let room_id = room_id_from_uuid(db, room_uuid).await?;
+
+    check_permission(db, user_id, room_id, permission).await?;
+
+    Ok((user_id, room_id))
+}
+
+/// Reads the `file` field of a multipart form, rejecting types `accepts` refuses
+/// before downloading anything. Returns the sanitized filename, MIME type and data.
+pub async fn read_file_field(
+    multipart: &mut Multipart,
+    max_size: usize,
+    accepts: impl Fn(&str) -> bool,
+) -> Result<(String, String, Vec<u8>), (StatusCode, String)> {
+    let mut field = multipart
+        .next_field()
+        .await
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.body_text()))?
+        .ok_or((StatusCode::BAD_REQUEST, "Missing `file` field".into()))?;
+
+    if field.name() != Some("file") {
+        return Err((StatusCode::BAD_REQUEST, "Missing `file` field".into()));
+    }
+
+    let filename = sanitize_filename(field.file_name().unwrap_or_default());
+    let mime_type = field
+        .content_type()
+        .unwrap_or("application/octet-stream")
+        .to_ascii_lowercase();
+
+    if !accepts(&mime_type) || HeaderValue::from_str(&mime_type).is_err() {
+        return Err((
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            format!("Files of type `{mime_type}` are not accepted"),
+        ));
+    }
+
+    let mut data = Vec::new();
+    while let Some(chunk) = field
+        .chunk()
+        .await
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.body_text()))?
+    {
+        if data.len() + chunk.len() > max_size {
+            return Err((
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!("Files are limited to {max_size} bytes"),
+            ));
+        }
+        data.extend_from_slice(&chunk);
+    }
+
+    if data.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "The file is empty".into()));
+    }
+
+    Ok((filename, mime_type, data))
+}
+
+/// Stores the `file` field of a multipart form. The returned uuid is then sent
+/// as the content of an `attachment` message.
+async fn upload_attachment(
+    Path(room_uuid): Path<Uuid>,
+    Extension(db): Extension<PgPool>,
+    Extension(storage): Extension<Storage>,
+    headers: HeaderMap,
+    mut multipart: Multipart,
+) -> Result<(StatusCode, Json<Attachment>), (StatusCode, String)> {
+    let (user_id, room_id) = member_of(&db, headers, room_uuid, Permission::Send).await?;
+
+    let (filename, mime_type, data) =
+        read_file_field(&mut multipart, storage.max_size, |mime_type| {
+            storage.allows(mime_type)
+        })
+        .await?;
+
+    let thumbnail = if storage::is_thumbnailable(&mime_type) {
+        let (image, format) = (data.clone(), mime_type.clone());
+        let thumbnail = tokio::task::spawn_blocking(move || storage::thumbnail(&image, &format))
+            .await
+            .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Upload failed".into()))?;
+
+        // Also proves the file is the image it claims to be
+        Some(thumbnail.ok_or((
+            StatusCode::BAD_REQUEST,
+            format!("The file is not a valid `{mime_type}` image"),
+        ))?)
+    } else {
+        None
+    };
+
+    let uuid = Uuid::now_v7();
+
+    let stored = async {
+        storage.save(uuid, &data).await?;
+        if let Some(thumbnail) = &thumbnail {
+            storage.save_thumbnail(uuid, thumbnail).await?;
+        }
+        Ok::<_, std::io::Error>(())
+    }
+    .await;
+
+    if let Err(e) = stored {
+        tracing::error!("failed to store attachment {uuid}: {e}");
+        storage.remove(uuid).await;
+        return Err((StatusCode::INTERNAL_SERVER_ERROR, "Upload failed".into()));
+    }
+
+    let attachment = sqlx::query_as::<_, Attachment>(
+        r#"
+        INSERT INTO attachment_ (uuid, room, uploader, filename, mime_type, size, thumbnail)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        RETURNING uuid, filename, mime_type, size, thumbnail
+        "#,
+    )
+    .bind(uuid)
+    .bind(room_id)
+    .bind(user_id)
+    .bind(&filename)
+    .bind(&mime_type)
+    .bind(data.len() as i64)
+    .bind(thumbnail.is_some())
+    .fetch_one(&db)
+    .await;
+
+    match attachment {
+        Ok(attachment) => Ok((StatusCode::CREATED, Json(attachment))),
+        Err(_) => {
+            storage.remove(uuid).await;
+            Err((StatusCode::INTERNAL_SERVER_ERROR, "Upload failed".into()))
+        }
+    }
+}
+
+async fn attachment_in_room(
+    db: &PgPool,
+    attachment_uuid: Uuid,
+    room_id: i32,
+) -> Result<Attachment, (StatusCode, String)> {
+    sqlx::query_as::<_, Attachment>(
+        r#"
+        SELECT uuid, filename, mime_type, size, thumbnail FROM attachment_
+        WHERE uuid = $1 AND room = $2
+        "#,
+    )
+    .bind(attachment_uuid)
+    .bind(room_id)
+    .fetch_optional(db)
+    .await
+    .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "DB error".into()))?
+    .ok_or((StatusCode::NOT_FOUND, "Attachment not found".into()))
+}
+
+/// Checks that an attachment message references a file its sender uploaded to the room.
+pub async fn check_attachment(
+    db: &PgPool,
+    attachment_uuid: Uuid,
+    room_id: i32,
+    user_id: i32,
+) -> Result<(), (StatusCode, String)> {
+    let uploader: i32 =
+        sqlx::query_scalar("SELECT uploader FROM attachment_ WHERE uuid = $1 AND room = $2")
+            .bind(attachment_uuid)
+            .bind(room_id)
+            .fetch_optional(db)
+            .await
+            .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "DB error".into()))?
+            .ok_or((StatusCode::NOT_FOUND, "Attachment not found".into()))?;
+
+    if uploader != user_id {
+        return Err((
+            StatusCode::FORBIDDEN,
+            "You can only send attachments you uploaded".into(),
+        ));
+    }
+
+    Ok(())
+}
+
+async fn download_attachment(
+    Path((room_uuid, attachment_uuid)): Path<(Uuid, Uuid)>,
+    Extension(db): Extension<PgPool>,
+    Extension(storage): Extension<Storage>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let (_, room_id) = member_of(&db, headers, room_uuid, Permission::Read).await?;
+    let attachment = attachment_in_room(&db, attachment_uuid, room_id).await?;
+
+    let data = storage
+        .load(attachment.uuid)
+        .await
+        .map_err(|_| (StatusCode::NOT_FOUND, "Attachment not found".into()))?;
+
+    // Only images we could decode are shown inline, everything else is downloaded
+    let disposition = if attachment.thumbnail {
+        "inline"
+    } else {
+        "attachment"
+    };
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, attachment.mime_type),
+            (
+                header::CONTENT_DISPOSITION,
+                content_disposition(disposition, &attachment.filename),
+            ),
+            (header::X_CONTENT_TYPE_OPTIONS, String::from("nosniff")),
+        ],
+        data,
+    ))
+}
+
+async fn download_thumbnail(
+    Path((room_uuid, attachment_uuid)): Path<(Uuid, Uuid)>,
+    Extension(db): Extension<PgPool>,
+    Extension(storage): Extension<Storage>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let (_, room_id) = member_of(&db, headers, room_uuid, Permission::Read).await?;
+    let attachment = attachment_in_room(&db, attachment_uuid, room_id).await?;
+
+    if !attachment.thumbnail {
+        return Err((
+            StatusCode::NOT_FOUND,
+            "No thumbnail for this attachment".into(),
+        ));
+    }
+
+    let data = storage
+        .load_thumbnail(attachment.uuid)
+        .await
+        .map_err(|_| (StatusCode::NOT_FOUND, "Attachment not found".into()))?;
+
+    Ok(([(header::CONTENT_TYPE, "image/png")], data))
+}
+
+/// Deletes the files uploaded long ago that no message shows, whether they were
+/// never sent or every message showing them is gone.
+pub async fn sweep_unsent_attachments(db: PgPool, storage: Storage) {
+    let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        let swept: Vec<Uuid> = match sqlx::query_scalar(
+            r#"
+            DELETE FROM attachment_ a
+            WHERE uploaded_at < now() - make_interval(secs => $1) AND NOT EXISTS (
+              SELECT 1 FROM message_ m
+              WHERE m.room = a.room AND m.message_type = 'attachment'
+                AND m.content = a.uuid::text AND m.deleted_at IS NULL
+            )
+            RETURNING uuid
+            "#,
+        )
+        .bind(UNSENT_ATTACHMENT_LIFETIME_SECS)
+        .fetch_all(&db)
+        .await
+        {
+            Ok(swept) => swept,
+            Err(e) => {
+                tracing::error!("could not sweep unsent attachments: {e}");
+                continue;
+            }
+        };
+
+        for uuid in swept {
+            storage.remove(uuid).await;
+        }
+    }
+}
@@ -0,0 +1,197 @@
+use axum::{
+    Extension, Json, Router,
+    extract::{Path, Query},
+    http::{HeaderMap, StatusCode},
+    routing::{get, post},
+};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    auth::verify_jwt,
+    db::{room_id_from_uuid, user_id_from_uuid, username_from_id},
+    realtime::{Event, Realtime},
+    routes::rooms::{Room, room_for},
+};
+
+/// A public room as listed in the directory
+#[derive(sqlx::FromRow, serde::Serialize)]
+pub struct DirectoryRoom {
+    pub uuid: Uuid,
+    pub name: String,
+    pub description: String,
+    pub topic: String,
+    pub avatar: Option<Uuid>,
+    pub members: i64,
+    /// Whether the caller is already a member
+    pub joined: bool,
+}
+
+#[derive(serde::Deserialize)]
+struct DirectoryQuery {
+    /// Matched against the name, description and topic
+    q: Option<String>,
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+pub fn routes() -> Router {
+    Router::new()
+        .route("/rooms/directory", get(list_directory))
+        .route("/rooms/{room_id}/join", post(join_room))
+}
+
+/// Escapes `LIKE` wildcards so searches match literally.
+fn like_pattern(search: &str) -> String {
+    let escaped = search
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    format!("%{escaped}%")
+}
+
+/// Public rooms, the most populated first.
+async fn list_directory(
+    Query(query): Query<DirectoryQuery>,
+    headers: HeaderMap,
+    Extension(db): Extension<PgPool>,
+) -> Result<Json<Vec<DirectoryRoom>>, (StatusCode, String)> {
+    let claims = verify_jwt(headers)?;
+
+    let user_id = user_id_from_uuid(&db, claims.sub).await?;
+
+    let limit = query.limit.unwrap_or(20).clamp(1, 50);
+    let offset = query.offset.unwrap_or(0).max(0);
+    let pattern = query
+        .q
+        .as_deref()
+        .map(str::trim)
+        .filter(|q| !q.is_empty())
+        .map(like_pattern);
+
+    let rooms = sqlx::query_as::<_, DirectoryRoom>(
+        r#"
+        SELECT
+            r.uuid,
+            r.name,
+            r.description,
+            r.topic,
+            r.avatar,
+            (SELECT count(*) FROM membership_ m WHERE m.room = r.id) AS members,
+            EXISTS (
+                SELECT 1 FROM membership_ m WHERE m.room = r.id AND m.user_id = $1
+            ) AS joined
+        FROM room_ r
+        WHERE r.public
+        AND (
+            $2::text IS NULL
+            OR r.name ILIKE $2
+            OR r.description ILIKE $2
+            OR r.topic ILIKE $2
+        )
+        ORDER BY members DESC, r.id
+        LIMIT $3 OFFSET $4
+        "#,
+    )
+    .bind(user_id)
+    .bind(pattern)
+    .bind(limit)
+    .bind(offset)
+    .fetch_all(&db)
+    .await
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Could not list public rooms".into(),
+        )
+    })?;
+
+    Ok(Json(rooms))
+}
+
+/// Joins a public room, which needs no invite. Leaving goes through `/rooms/{uuid}/leave`.
+async fn join_room(
+    Path(room_uuid): Path<Uuid>,
+    headers: HeaderMap,
+    Extension(db): Extension<PgPool>,
+    Extension(realtime): Extension<Realtime>,
+) -> Result<(StatusCode, Json<Room>), (StatusCode, String)> {
+    let claims = verify_jwt(headers)?;
+
+    let user_id = user_id_from_uuid(&db, claims.sub).await?;
+    let room_id = room_id_from_uuid(&db, room_uuid).await?;
+
+    let joined = sqlx::query(
+        r#"
+        INSERT INTO membership_ (user_id, room)
+        SELECT $1, r.id FROM room_ r WHERE r.id = $2 AND r.public
+        ON CONFLICT DO NOTHING
+        "#,
+    )
+    .bind(user_id)
+    .bind(room_id)
+    .execute(&db)
+    .await
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Error creating room membership".into(),
+        )
+    })?
+    .rows_affected();
+
+    if joined == 0 {
+        let public: bool = sqlx::query_scalar("SELECT public FROM room_ WHERE id = $1")
+            .bind(room_id)
+            .fetch_one(&db)
+            .await
+            .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "DB error".into()))?;
+
+        return Err(if public {
+            (
+                StatusCode::CONFLICT,
+                "You are already a member of this room".into(),
+            )
+        } else {
+            (
+                StatusCode::FORBIDDEN,
+                "This room can only be joined with an invite".into(),
+            )
+        });
+    }
+
+    realtime.publish(
+        room_id,
+        Event::MemberJoined {
+            room: room_uuid,
+            user: username_from_id(&db, user_id).await?,
+        },
+    );
+
+    Ok((
+        StatusCode::CREATED,
+        Json(room_for(&db, user_id, room_id).await?),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_searches_match_anywhere() {
+        assert_eq!(like_pattern("rust"), "%rust%");
+        assert_eq!(like_pattern(""), "%%");
+    }
+
+    #[test]
+    fn wildcards_are_escaped() {
+        assert_eq!(like_pattern("100%"), "%100\\%%");
+        assert_eq!(like_pattern("snake_case"), "%snake\\_case%");
+    }
+
+    #[test]
+    fn backslashes_are_escaped_first() {
+        assert_eq!(like_pattern("a\\%"), "%a\\\\\\%%");
+    }
+}
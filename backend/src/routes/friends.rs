@@ -7,12 +7,14 @@ use sqlx::PgPool;
 use uuid::Uuid;
 
 use crate::db::{user_id_from_uuid, username_from_id, username_from_uuid};
+use crate::routes::presence::{PRESENCE, Presence, user_presence};
 use crate::{auth::verify_jwt, db::id_from_username};
 
 #[derive(sqlx::FromRow, serde::Serialize)]
 pub struct Friend {
     pub uuid: Uuid,
     pub username: String,
+    pub presence: Presence,
 }
 
 #[derive(sqlx::FromRow, serde::Serialize)]
@@ -52,15 +54,15 @@ async fn list_friends(
     let claims = verify_jwt(headers)?;
     let user_id = user_id_from_uuid(&db, claims.sub).await?;
 
-    let friends = sqlx::query_as::<_, Friend>(
+    let friends = sqlx::query_as::<_, Friend>(&format!(
         r#"
-        SELECT u.uuid, u.username
+        SELECT u.uuid, u.username, {PRESENCE} AS presence
         FROM friendship_
         JOIN user_ u
           ON (u.id = friendship_.user_first AND friendship_.user_second = $1)
           OR (u.id = friendship_.user_second AND friendship_.user_first = $1)
-        "#,
-    )
+        "#
+    ))
     .bind(user_id)
     .fetch_all(&db)
     .await
@@ -220,6 +222,7 @@ async fn accept_request(
         Json(Friend {
             uuid: payload.sender_uuid,
             username: username_from_uuid(&db, payload.sender_uuid).await?,
+            presence: user_presence(&db, sender_id).await?,
         }),
     ))
 }
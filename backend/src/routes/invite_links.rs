@@ -0,0 +1,402 @@
+use axum::{
+    Extension, Json, Router,
+    extract::Path,
+    http::{HeaderMap, StatusCode},
+    routing::{delete, get, post},
+};
+use password_hash::rand_core::{OsRng, RngCore};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    auth::verify_jwt,
+    db::{room_id_from_uuid, user_id_from_uuid, username_from_id},
+    realtime::{Event, Realtime},
+    routes::{
+        messages::TIMESTAMP_FORMAT,
+        roles::{Permission, check_permission, room_role},
+        rooms::{Room, room_for},
+    },
+};
+
+const CODE_LENGTH: usize = 12;
+const CODE_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+/// Longest lifetime of a link, in seconds
+const MAX_LINK_LIFETIME: i64 = 60 * 60 * 24 * 365;
+
+#[derive(serde::Serialize)]
+pub struct InviteLink {
+    pub code: String,
+    pub room: Uuid,
+    pub creator: String,
+    pub created_at: String,
+    pub expires_at: Option<String>,
+    pub max_uses: Option<i32>,
+    pub uses: i32,
+    pub revoked: bool,
+}
+
+#[derive(sqlx::FromRow)]
+struct InviteLinkRow {
+    code: String,
+    room: Uuid,
+    creator: String,
+    created_at: chrono::NaiveDateTime,
+    expires_at: Option<chrono::NaiveDateTime>,
+    max_uses: Option<i32>,
+    uses: i32,
+    revoked: bool,
+}
+
+impl From<InviteLinkRow> for InviteLink {
+    fn from(l: InviteLinkRow) -> Self {
+        InviteLink {
+            code: l.code,
+            room: l.room,
+            creator: l.creator,
+            created_at: l.created_at.format(TIMESTAMP_FORMAT).to_string(),
+            expires_at: l.expires_at.map(|t| t.format(TIMESTAMP_FORMAT).to_string()),
+            max_uses: l.max_uses,
+            uses: l.uses,
+            revoked: l.revoked,
+        }
+    }
+}
+
+/// Someone who joined through a link
+#[derive(serde::Serialize)]
+pub struct InviteLinkUse {
+    pub uuid: Uuid,
+    pub username: String,
+    pub used_at: String,
+}
+
+/// Both limits are optional, links without them stay valid until revoked
+#[derive(serde::Deserialize)]
+pub struct NewInviteLinkPayload {
+    /// Lifetime in seconds
+    pub expires_in: Option<i64>,
+    pub max_uses: Option<i32>,
+}
+
+const SELECT_LINKS: &str = r#"
+    SELECT
+        l.code,
+        r.uuid AS room,
+        u.username AS creator,
+        l.created_at,
+        l.expires_at,
+        l.max_uses,
+        l.uses,
+        l.revoked_at IS NOT NULL AS revoked
+    FROM invite_link_ l
+    JOIN room_ r ON r.id = l.room
+    JOIN user_ u ON u.id = l.creator
+"#;
+
+pub fn routes() -> Router {
+    Router::new()
+        .route("/rooms/{room_id}/links", get(list_links))
+        .route("/rooms/{room_id}/links", post(create_link))
+        .route("/rooms/{room_id}/links/{code}", delete(revoke_link))
+        .route("/rooms/{room_id}/links/{code}/uses", get(list_link_uses))
+        .route("/invites/{code}", post(redeem_link))
+}
+
+fn generate_code() -> String {
+    let mut bytes = [0u8; CODE_LENGTH];
+    OsRng.fill_bytes(&mut bytes);
+
+    bytes
+        .iter()
+        .map(|b| CODE_ALPHABET[(b & 63) as usize] as char)
+        .collect()
+}
+
+/// Resolves the caller and room, checking the caller manages the room's links.
+async fn link_manager(
+    db: &PgPool,
+    headers: HeaderMap,
+    room_uuid: Uuid,
+) -> Result<(i32, i32), (StatusCode, String)> {
+    let claims = verify_jwt(headers)?;
+
+    let user_id = user_id_from_uuid(db, claims.sub).await?;
+    let room_id = room_id_from_uuid(db, room_uuid).await?;
+
+    check_permission(db, user_id, room_id, Permission::ManageInviteLinks).await?;
+
+    Ok((user_id, room_id))
+}
+
+async fn create_link(
+    Path(room_uuid): Path<Uuid>,
+    headers: HeaderMap,
+    Extension(db): Extension<PgPool>,
+    Json(payload): Json<NewInviteLinkPayload>,
+) -> Result<(StatusCode, Json<InviteLink>), (StatusCode, String)> {
+    let (user_id, room_id) = link_manager(&db, headers, room_uuid).await?;
+
+    let (global, direct): (bool, bool) =
+        sqlx::query_as("SELECT global, direct FROM room_ WHERE id = $1")
+            .bind(room_id)
+            .fetch_one(&db)
+            .await
+            .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "DB error".into()))?;
+
+    if global || direct {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Global rooms and direct messages have no invite links".into(),
+        ));
+    }
+
+    if payload
+        .expires_in
+        .is_some_and(|secs| secs <= 0 || secs > MAX_LINK_LIFETIME)
+    {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("Links can last between 1 and {MAX_LINK_LIFETIME} seconds"),
+        ));
+    }
+
+    if payload.max_uses.is_some_and(|uses| uses <= 0) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Links must allow at least one use".into(),
+        ));
+    }
+
+    let link_id: i32 = sqlx::query_scalar(
+        r#"
+        INSERT INTO invite_link_ (code, room, creator, expires_at, max_uses)
+        VALUES ($1, $2, $3, now() + make_interval(secs => $4), $5)
+        RETURNING id
+        "#,
+    )
+    .bind(generate_code())
+    .bind(room_id)
+    .bind(user_id)
+    .bind(payload.expires_in.map(|secs| secs as f64))
+    .bind(payload.max_uses)
+    .fetch_one(&db)
+    .await
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Could not create invite link".into(),
+        )
+    })?;
+
+    let link = sqlx::query_as::<_, InviteLinkRow>(&format!("{SELECT_LINKS} WHERE l.id = $1"))
+        .bind(link_id)
+        .fetch_one(&db)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "DB error".into()))?;
+
+    Ok((StatusCode::CREATED, Json(link.into())))
+}
+
+/// Every link of a room, revoked and expired ones included.
+async fn list_links(
+    Path(room_uuid): Path<Uuid>,
+    headers: HeaderMap,
+    Extension(db): Extension<PgPool>,
+) -> Result<Json<Vec<InviteLink>>, (StatusCode, String)> {
+    let (_, room_id) = link_manager(&db, headers, room_uuid).await?;
+
+    let links = sqlx::query_as::<_, InviteLinkRow>(&format!(
+        "{SELECT_LINKS} WHERE l.room = $1 ORDER BY l.created_at DESC"
+    ))
+    .bind(room_id)
+    .fetch_all(&db)
+    .await
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Could not list invite links".into(),
+        )
+    })?;
+
+    Ok(Json(links.into_iter().map(InviteLink::from).collect()))
+}
+
+/// Stops a link from being used. Revoked links are kept for their history.
+async fn revoke_link(
+    Path((room_uuid, code)): Path<(Uuid, String)>,
+    headers: HeaderMap,
+    Extension(db): Extension<PgPool>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let (user_id, room_id) = link_manager(&db, headers, room_uuid).await?;
+
+    let rows = sqlx::query(
+        r#"
+        UPDATE invite_link_ SET revoked_at = now(), revoked_by = $1
+        WHERE code = $2 AND room = $3 AND revoked_at IS NULL
+        "#,
+    )
+    .bind(user_id)
+    .bind(&code)
+    .bind(room_id)
+    .execute(&db)
+    .await
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Could not revoke invite link".into(),
+        )
+    })?
+    .rows_affected();
+
+    if rows == 0 {
+        return Err((StatusCode::NOT_FOUND, "No such invite link".into()));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Who joined through a link, oldest first.
+async fn list_link_uses(
+    Path((room_uuid, code)): Path<(Uuid, String)>,
+    headers: HeaderMap,
+    Extension(db): Extension<PgPool>,
+) -> Result<Json<Vec<InviteLinkUse>>, (StatusCode, String)> {
+    let (_, room_id) = link_manager(&db, headers, room_uuid).await?;
+
+    let uses = sqlx::query_as::<_, (Uuid, String, chrono::NaiveDateTime)>(
+        r#"
+        SELECT u.uuid, u.username, lu.used_at
+        FROM invite_link_use_ lu
+        JOIN invite_link_ l ON l.id = lu.link
+        JOIN user_ u ON u.id = lu.user_id
+        WHERE l.code = $1 AND l.room = $2
+        ORDER BY lu.used_at
+        "#,
+    )
+    .bind(&code)
+    .bind(room_id)
+    .fetch_all(&db)
+    .await
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Could not list invite link uses".into(),
+        )
+    })?;
+
+    Ok(Json(
+        uses.into_iter()
+            .map(|(uuid, username, used_at)| InviteLinkUse {
+                uuid,
+                username,
+                used_at: used_at.format(TIMESTAMP_FORMAT).to_string(),
+            })
+            .collect(),
+    ))
+}
+
+/// Joins the room of a link, counting one use of it.
+async fn redeem_link(
+    Path(code): Path<String>,
+    headers: HeaderMap,
+    Extension(db): Extension<PgPool>,
+    Extension(realtime): Extension<Realtime>,
+) -> Result<(StatusCode, Json<Room>), (StatusCode, String)> {
+    let claims = verify_jwt(headers)?;
+
+    let user_id = user_id_from_uuid(&db, claims.sub).await?;
+
+    let mut tx = db
+        .begin()
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "DB error".into()))?;
+
+    let (link_id, room_id, room_uuid, creator, valid): (i32, i32, Uuid, i32, bool) =
+        sqlx::query_as(
+            r#"
+        SELECT l.id, l.room, r.uuid, l.creator,
+            l.revoked_at IS NULL
+            AND (l.expires_at IS NULL OR l.expires_at > now())
+            AND (l.max_uses IS NULL OR l.uses < l.max_uses)
+        FROM invite_link_ l
+        JOIN room_ r ON r.id = l.room
+        WHERE l.code = $1
+        FOR UPDATE OF l
+        "#,
+        )
+        .bind(&code)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "DB error".into()))?
+        .ok_or((StatusCode::NOT_FOUND, "No such invite link".into()))?;
+
+    // Links stop working once their creator was demoted or left the room
+    let creator_allowed = room_role(&db, creator, room_id)
+        .await?
+        .is_some_and(|role| role.can(Permission::ManageInviteLinks));
+
+    if !valid || !creator_allowed {
+        return Err((
+            StatusCode::GONE,
+            "This invite link has expired, been used up or been revoked".into(),
+        ));
+    }
+
+    let joined = sqlx::query(
+        "INSERT INTO membership_ (user_id, room) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+    )
+    .bind(user_id)
+    .bind(room_id)
+    .execute(&mut *tx)
+    .await
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Error creating room membership".into(),
+        )
+    })?
+    .rows_affected();
+
+    if joined == 0 {
+        return Err((
+            StatusCode::CONFLICT,
+            "You are already a member of this room".into(),
+        ));
+    }
+
+    sqlx::query("UPDATE invite_link_ SET uses = uses + 1 WHERE id = $1")
+        .bind(link_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "DB error".into()))?;
+
+    sqlx::query("INSERT INTO invite_link_use_ (link, user_id) VALUES ($1, $2)")
+        .bind(link_id)
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "DB error".into()))?;
+
+    tx.commit().await.map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Could not join room".into(),
+        )
+    })?;
+
+    realtime.publish(
+        room_id,
+        Event::MemberJoined {
+            room: room_uuid,
+            user: username_from_id(&db, user_id).await?,
+        },
+    );
+
+    Ok((
+        StatusCode::CREATED,
+        Json(room_for(&db, user_id, room_id).await?),
+    ))
+}
@@ -0,0 +1,303 @@
+use axum::{
+    Extension, Json, Router,
+    http::{HeaderMap, StatusCode},
+    routing::get,
+};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    auth::verify_jwt,
+    db::{id_from_username, user_id_from_uuid},
+    realtime::{Event, Realtime},
+    routes::{messages::TIMESTAMP_FORMAT, roles::room_role, rooms::ROOM_NAME},
+};
+
+/// Most unread mentions listed at once
+const MAX_UNREAD_MENTIONS: i64 = 100;
+
+/// A message mentioning the user
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct Mention {
+    /// The message the user was mentioned in
+    pub uuid: Uuid,
+    pub room: Uuid,
+    pub room_name: String,
+    pub sender: String,
+    pub content: String,
+    pub sent_at: String,
+}
+
+#[derive(sqlx::FromRow)]
+struct MentionRow {
+    uuid: Uuid,
+    room: Uuid,
+    room_name: String,
+    sender: String,
+    content: String,
+    sent_at: chrono::NaiveDateTime,
+}
+
+impl From<MentionRow> for Mention {
+    fn from(m: MentionRow) -> Self {
+        Mention {
+            uuid: m.uuid,
+            room: m.room,
+            room_name: m.room_name,
+            sender: m.sender,
+            content: m.content,
+            sent_at: m.sent_at.format(TIMESTAMP_FORMAT).to_string(),
+        }
+    }
+}
+
+pub fn routes() -> Router {
+    Router::new().route("/mentions", get(list_mentions))
+}
+
+/// Unread mentions of user `$1` in the rooms they can still read, followed by `clauses`.
+fn select_mentions(clauses: &str) -> String {
+    format!(
+        r#"
+        SELECT
+            m.uuid,
+            r.uuid AS room,
+            {ROOM_NAME} AS room_name,
+            u.username AS sender,
+            m.content,
+            m.sent_at
+        FROM mention_ mn
+        JOIN message_ m ON m.id = mn.message
+        JOIN room_ r ON r.id = m.room
+        JOIN user_ u ON u.id = m.sender
+        WHERE mn.user_id = $1
+        AND mn.read_at IS NULL
+        AND m.deleted_at IS NULL
+        AND (r.global OR EXISTS (
+            SELECT 1
+            FROM membership_ ms
+            WHERE ms.user_id = $1 AND ms.room = r.id
+        ))
+        {clauses}
+        "#
+    )
+}
+
+fn is_username_char(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '_' | '-' | '.')
+}
+
+/// Whether `@username` would be read back whole as a mention of that user.
+pub fn is_valid_username(username: &str) -> bool {
+    !username.is_empty() && username.chars().all(is_username_char) && !username.ends_with('.')
+}
+
+/// Usernames written as `@username` in a message, without duplicates.
+fn mentioned_usernames(content: &str) -> Vec<&str> {
+    let mut usernames = Vec::new();
+
+    for (i, _) in content.match_indices('@') {
+        // Not a mention when glued to a word, as in email addresses
+        if content[..i]
+            .chars()
+            .next_back()
+            .is_some_and(char::is_alphanumeric)
+        {
+            continue;
+        }
+
+        let rest = &content[i + 1..];
+        let end = rest
+            .find(|c: char| !is_username_char(c))
+            .unwrap_or(rest.len());
+
+        // A trailing dot ends the sentence rather than the username
+        let username = rest[..end].trim_end_matches('.');
+
+        if !username.is_empty() && !usernames.contains(&username) {
+            usernames.push(username);
+        }
+    }
+
+    usernames
+}
+
+/// Records the members mentioned in a message and notifies each of them directly,
+/// whether or not they follow the room. Someone mentioned again, as when a message is
+/// edited, is only notified once. The message is already stored by then, so failures
+/// are logged rather than returned.
+pub async fn record_mentions(
+    db: &PgPool,
+    realtime: &Realtime,
+    sender_id: i32,
+    room_id: i32,
+    message_id: i64,
+    content: &str,
+) {
+    for username in mentioned_usernames(content) {
+        if let Err((_, error)) =
+            record_mention(db, realtime, sender_id, room_id, message_id, username).await
+        {
+            tracing::warn!("could not record mention of {username}: {error}");
+        }
+    }
+}
+
+async fn record_mention(
+    db: &PgPool,
+    realtime: &Realtime,
+    sender_id: i32,
+    room_id: i32,
+    message_id: i64,
+    username: &str,
+) -> Result<(), (StatusCode, String)> {
+    // Unknown usernames are just text
+    let Ok(user_id) = id_from_username(db, username.to_string()).await else {
+        return Ok(());
+    };
+
+    if user_id == sender_id || room_role(db, user_id, room_id).await?.is_none() {
+        return Ok(());
+    }
+
+    let inserted = sqlx::query(
+        "INSERT INTO mention_ (message, user_id) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+    )
+    .bind(message_id)
+    .bind(user_id)
+    .execute(db)
+    .await
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Could not record mention".into(),
+        )
+    })?
+    .rows_affected();
+
+    if inserted == 0 {
+        return Ok(());
+    }
+
+    let mention = sqlx::query_as::<_, MentionRow>(&select_mentions("AND m.id = $2"))
+        .bind(user_id)
+        .bind(message_id)
+        .fetch_one(db)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "DB error".into()))?;
+
+    realtime.publish_to_user(user_id, Event::Mentioned(Box::new(mention.into())));
+
+    Ok(())
+}
+
+/// Marks the caller's mentions in a room as read, up to and including a message.
+pub async fn mark_mentions_read(
+    db: &PgPool,
+    user_id: i32,
+    room_id: i32,
+    message_id: i64,
+) -> Result<(), (StatusCode, String)> {
+    sqlx::query(
+        r#"
+        UPDATE mention_ mn
+        SET read_at = now()
+        FROM message_ m
+        WHERE m.id = mn.message
+        AND mn.user_id = $1
+        AND mn.read_at IS NULL
+        AND m.room = $2
+        AND m.id <= $3
+        "#,
+    )
+    .bind(user_id)
+    .bind(room_id)
+    .bind(message_id)
+    .execute(db)
+    .await
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Could not mark mentions as read".into(),
+        )
+    })?;
+
+    Ok(())
+}
+
+/// The caller's unread mentions across rooms, newest first. Mentions are read once
+/// the caller's read cursor of the room reaches them.
+async fn list_mentions(
+    headers: HeaderMap,
+    Extension(db): Extension<PgPool>,
+) -> Result<Json<Vec<Mention>>, (StatusCode, String)> {
+    let claims = verify_jwt(headers)?;
+
+    let user_id = user_id_from_uuid(&db, claims.sub).await?;
+
+    let mentions = sqlx::query_as::<_, MentionRow>(&select_mentions("ORDER BY m.id DESC LIMIT $2"))
+        .bind(user_id)
+        .bind(MAX_UNREAD_MENTIONS)
+        .fetch_all(&db)
+        .await
+        .map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Could not list mentions".into(),
+            )
+        })?;
+
+    Ok(Json(mentions.into_iter().map(Mention::from).collect()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_mentions_anywhere_in_a_message() {
+        assert_eq!(
+            mentioned_usernames("@alice can you ask @bob_2?"),
+            ["alice", "bob_2"]
+        );
+    }
+
+    #[test]
+    fn skips_duplicates_and_lone_at_signs() {
+        assert_eq!(mentioned_usernames("@alice @ @alice"), ["alice"]);
+        assert!(mentioned_usernames("meet @ 5").is_empty());
+    }
+
+    #[test]
+    fn ignores_email_addresses() {
+        assert!(mentioned_usernames("mail alice@example.com").is_empty());
+    }
+
+    #[test]
+    fn keeps_dots_inside_usernames_but_not_at_the_end() {
+        assert_eq!(
+            mentioned_usernames("thanks @jean.luc. and @carol."),
+            ["jean.luc", "carol"]
+        );
+    }
+
+    #[test]
+    fn handles_non_ascii_text() {
+        assert_eq!(mentioned_usernames("é@x, (@zoé)"), ["zoé"]);
+    }
+
+    #[test]
+    fn valid_usernames_can_be_mentioned() {
+        for username in ["alice", "jean.luc", "bob_2", "zoé", "a-b"] {
+            assert!(is_valid_username(username));
+            assert_eq!(mentioned_usernames(&format!("hi @{username}.")), [username]);
+        }
+    }
+
+    #[test]
+    fn rejects_usernames_that_cannot_be_mentioned() {
+        for username in ["", "jean luc", "carol.", "a@b", "x!"] {
+            assert!(!is_valid_username(username));
+        }
+    }
+}
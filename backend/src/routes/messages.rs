@@ -2,39 +2,227 @@ use axum::{
     Extension, Json, Router,
     extract::{Path, Query},
     http::{HeaderMap, StatusCode},
-    routing::{get, post},
+    routing::{delete, get, post, put},
 };
 use sqlx::PgPool;
 use uuid::Uuid;
 
-use crate::{auth::verify_jwt, db::room_id_from_uuid, routes::rooms::is_member};
 use crate::{
-    db::{user_id_from_uuid, username_from_uuid},
-    realtime::Realtime,
+    auth::verify_jwt,
+    db::room_id_from_uuid,
+    routes::{
+        attachments::{Attachment, check_attachment},
+        mentions::record_mentions,
+        reactions::{Reaction, load_reactions},
+        roles::{Permission, check_permission},
+    },
+    storage::Storage,
 };
+use crate::{
+    db::user_id_from_uuid,
+    realtime::{Event, Realtime},
+};
+
+pub const TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+const MAX_CONTENT_LENGTH: usize = 4000;
+
+/// Length of the parent excerpt shown with replies
+const SNIPPET_LENGTH: i32 = 100;
+
+/// Shared by every query returning `MessageRow`s, callers append their own clauses.
+/// Deleted messages come back as tombstones with their content blanked.
+const SELECT_MESSAGES: &str = r#"
+        SELECT
+            m.uuid,
+            u.username AS sender,
+            r.uuid AS room,
+            m.message_type,
+            CASE WHEN m.deleted_at IS NULL THEN m.content ELSE '' END AS content,
+            m.sent_at,
+            m.edited_at,
+            m.deleted_at,
+            p.uuid AS parent_uuid,
+            pu.username AS parent_sender,
+            CASE WHEN p.deleted_at IS NULL THEN left(p.content, $SNIPPET) ELSE '' END
+                AS parent_snippet,
+            a.uuid AS attachment_uuid,
+            a.filename AS attachment_filename,
+            a.mime_type AS attachment_mime_type,
+            a.size AS attachment_size,
+            a.thumbnail AS attachment_thumbnail
+        FROM message_ m
+        JOIN user_ u ON u.id = m.sender
+        JOIN room_ r ON r.id = m.room
+        LEFT JOIN message_ p ON p.id = m.parent
+        LEFT JOIN user_ pu ON pu.id = p.sender
+        LEFT JOIN attachment_ a ON m.message_type = 'attachment'
+            AND m.deleted_at IS NULL
+            AND a.uuid::text = m.content
+"#;
+
+/// Stored in `message_.message_type`
+#[derive(sqlx::Type, serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[sqlx(type_name = "varchar", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum MessageKind {
+    Text,
+    /// Posted by the server itself, never accepted from clients
+    System,
+    Reply,
+    /// `content` holds the uuid of the attached file
+    Attachment,
+}
+
+impl std::str::FromStr for MessageKind {
+    type Err = (StatusCode, String);
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(MessageKind::Text),
+            "system" => Ok(MessageKind::System),
+            "reply" => Ok(MessageKind::Reply),
+            "attachment" => Ok(MessageKind::Attachment),
+            _ => Err((
+                StatusCode::BAD_REQUEST,
+                format!("Unknown message type `{s}`"),
+            )),
+        }
+    }
+}
+
+impl MessageKind {
+    /// Checks the content of a message sent by a client.
+    pub fn validate(&self, content: &str) -> Result<(), (StatusCode, String)> {
+        match self {
+            MessageKind::Text | MessageKind::Reply => {
+                if content.trim().is_empty() {
+                    return Err((
+                        StatusCode::BAD_REQUEST,
+                        "Message content cannot be empty".into(),
+                    ));
+                }
+                if content.chars().count() > MAX_CONTENT_LENGTH {
+                    return Err((
+                        StatusCode::BAD_REQUEST,
+                        format!("Messages are limited to {MAX_CONTENT_LENGTH} characters"),
+                    ));
+                }
+                Ok(())
+            }
+            MessageKind::Attachment => Uuid::parse_str(content).map(|_| ()).map_err(|_| {
+                (
+                    StatusCode::BAD_REQUEST,
+                    "Attachment messages must contain an attachment uuid".into(),
+                )
+            }),
+            MessageKind::System => Err((
+                StatusCode::BAD_REQUEST,
+                "System messages cannot be sent by clients".into(),
+            )),
+        }
+    }
+
+    /// Only messages written by hand can be edited.
+    pub fn is_editable(&self) -> bool {
+        matches!(self, MessageKind::Text | MessageKind::Reply)
+    }
+}
 
 #[derive(sqlx::FromRow, serde::Serialize, Debug)]
 pub struct MessageRow {
     pub uuid: Uuid,
     pub sender: String,
-    pub message_type: String,
+    pub room: Uuid,
+    pub message_type: MessageKind,
     pub content: String,
     pub sent_at: chrono::NaiveDateTime,
+    pub edited_at: Option<chrono::NaiveDateTime>,
+    pub deleted_at: Option<chrono::NaiveDateTime>,
+    pub parent_uuid: Option<Uuid>,
+    pub parent_sender: Option<String>,
+    pub parent_snippet: Option<String>,
+    pub attachment_uuid: Option<Uuid>,
+    pub attachment_filename: Option<String>,
+    pub attachment_mime_type: Option<String>,
+    pub attachment_size: Option<i64>,
+    pub attachment_thumbnail: Option<bool>,
 }
 
-#[derive(sqlx::FromRow, serde::Serialize, Debug, Clone)]
+#[derive(sqlx::FromRow, serde::Serialize, serde::Deserialize, Debug, Clone)]
 pub struct Message {
     pub uuid: Uuid,
     pub sender: String,
-    pub message_type: String,
+    pub room: Uuid,
+    pub message_type: MessageKind,
     pub content: String,
     pub sent_at: String,
+    pub edited_at: Option<String>,
+    pub deleted_at: Option<String>,
+    /// Set on replies
+    pub parent: Option<MessageParent>,
+    /// Set on attachment messages
+    pub attachment: Option<Attachment>,
+    /// Filled in for the user fetching the message, empty on live events
+    #[serde(default)]
+    pub reactions: Vec<Reaction>,
+}
+
+/// The message a reply points to, with an excerpt to quote
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct MessageParent {
+    pub uuid: Uuid,
+    pub sender: String,
+    pub snippet: String,
+}
+
+impl From<MessageRow> for Message {
+    fn from(m: MessageRow) -> Self {
+        Message {
+            uuid: m.uuid,
+            sender: m.sender,
+            room: m.room,
+            message_type: m.message_type,
+            content: m.content,
+            sent_at: m.sent_at.format(TIMESTAMP_FORMAT).to_string(),
+            edited_at: m.edited_at.map(|t| t.format(TIMESTAMP_FORMAT).to_string()),
+            deleted_at: m.deleted_at.map(|t| t.format(TIMESTAMP_FORMAT).to_string()),
+            parent: m.parent_uuid.map(|uuid| MessageParent {
+                uuid,
+                sender: m.parent_sender.unwrap_or_default(),
+                snippet: m.parent_snippet.unwrap_or_default(),
+            }),
+            attachment: m.attachment_uuid.map(|uuid| Attachment {
+                uuid,
+                filename: m.attachment_filename.unwrap_or_default(),
+                mime_type: m.attachment_mime_type.unwrap_or_default(),
+                size: m.attachment_size.unwrap_or_default(),
+                thumbnail: m.attachment_thumbnail.unwrap_or_default(),
+            }),
+            reactions: Vec::new(),
+        }
+    }
+}
+
+#[derive(serde::Serialize)]
+pub struct MessageRevision {
+    pub content: String,
+    /// When this content was replaced by an edit
+    pub replaced_at: String,
 }
 
 #[derive(serde::Deserialize)]
 pub struct NewMessagePayload {
     pub message_type: String,
     pub content: String,
+    /// Message being replied to, required for `reply` messages
+    #[serde(default)]
+    pub parent: Option<Uuid>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct EditMessagePayload {
+    pub content: String,
 }
 
 #[derive(serde::Deserialize)]
@@ -47,6 +235,73 @@ pub fn routes() -> Router {
     Router::new()
         .route("/messages/{room_uuid}", get(list_messages))
         .route("/messages/{room_uuid}", post(create_message))
+        .route("/messages/{room_uuid}/{message_uuid}", put(edit_message))
+        .route(
+            "/messages/{room_uuid}/{message_uuid}",
+            delete(delete_message),
+        )
+        .route(
+            "/messages/{room_uuid}/{message_uuid}/history",
+            get(list_revisions),
+        )
+        .route(
+            "/messages/{room_uuid}/{message_uuid}/replies",
+            get(list_replies),
+        )
+}
+
+/// Builds a query on top of `SELECT_MESSAGES`.
+fn select_messages(clauses: &str) -> String {
+    format!("{SELECT_MESSAGES} {clauses}").replace("$SNIPPET", &SNIPPET_LENGTH.to_string())
+}
+
+/// One page of a room's messages, oldest first, optionally limited to the replies to `thread`.
+async fn page_messages(
+    db: &PgPool,
+    viewer: i32,
+    room_id: i32,
+    thread: Option<i64>,
+    query: MessageFetchQuery,
+) -> Result<Vec<Message>, (StatusCode, String)> {
+    let limit: i32 = query.limit.unwrap_or(30).clamp(1, 80);
+
+    let messages = sqlx::query_as::<_, MessageRow>(&select_messages(
+        r#"
+        WHERE m.room = $1
+        AND ($2::uuid IS NULL OR m.id < (SELECT id FROM message_ WHERE uuid = $2))
+        AND ($4::bigint IS NULL OR m.parent = $4)
+        ORDER BY m.id DESC
+        LIMIT $3
+        "#,
+    ))
+    .bind(room_id)
+    .bind(query.before)
+    .bind(limit)
+    .bind(thread)
+    .fetch_all(db)
+    .await
+    .map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to list messages: {e}"),
+        )
+    })?;
+
+    let mut messages: Vec<Message> = messages.into_iter().map(Message::from).collect();
+
+    messages.reverse();
+    load_reactions(db, viewer, &mut messages).await?;
+
+    Ok(messages)
+}
+
+async fn message_by_id(db: &PgPool, message_id: i64) -> Result<Message, (StatusCode, String)> {
+    sqlx::query_as::<_, MessageRow>(&select_messages("WHERE m.id = $1"))
+        .bind(message_id)
+        .fetch_one(db)
+        .await
+        .map(Message::from)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "DB error".into()))
 }
 
 async fn list_messages(
@@ -60,37 +315,73 @@ async fn list_messages(
     let user_id = user_id_from_uuid(&db, claims.sub).await?;
     let room_id = room_id_from_uuid(&db, room_uuid).await?;
 
-    if !is_member(user_id, room_id, &db).await {
-        return Err((
-            StatusCode::FORBIDDEN,
-            String::from("You are not a member of this room"),
-        ));
-    }
+    check_permission(&db, user_id, room_id, Permission::Read).await?;
+
+    let messages = page_messages(&db, user_id, room_id, None, query).await?;
 
-    let limit: i32 = query.limit.unwrap_or(30).abs().min(80);
+    Ok(Json(messages))
+}
+
+/// Replies to a message, paginated like `list_messages`.
+async fn list_replies(
+    Path((room_uuid, message_uuid)): Path<(Uuid, Uuid)>,
+    Query(query): Query<MessageFetchQuery>,
+    headers: HeaderMap,
+    Extension(db): Extension<PgPool>,
+) -> Result<Json<Vec<Message>>, (StatusCode, String)> {
+    let claims = verify_jwt(headers)?;
+
+    let user_id = user_id_from_uuid(&db, claims.sub).await?;
+    let room_id = room_id_from_uuid(&db, room_uuid).await?;
+
+    check_permission(&db, user_id, room_id, Permission::Read).await?;
+
+    let parent_id = message_id_in_room(&db, message_uuid, room_id).await?;
+    let messages = page_messages(&db, user_id, room_id, Some(parent_id), query).await?;
+
+    Ok(Json(messages))
+}
 
-    let messages = sqlx::query_as::<_, MessageRow>(
+async fn message_id_in_room(
+    db: &PgPool,
+    message_uuid: Uuid,
+    room_id: i32,
+) -> Result<i64, (StatusCode, String)> {
+    sqlx::query_scalar("SELECT id FROM message_ WHERE uuid = $1 AND room = $2")
+        .bind(message_uuid)
+        .bind(room_id)
+        .fetch_optional(db)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "DB error".into()))?
+        .ok_or((StatusCode::NOT_FOUND, "Message not found".into()))
+}
+
+/// Messages posted in a room after `after` (or since the start of the room), oldest first.
+/// Used to replay what a realtime subscriber missed.
+pub async fn messages_after(
+    db: &PgPool,
+    viewer: i32,
+    room_id: i32,
+    after: Option<Uuid>,
+    limit: i32,
+) -> Result<Vec<Message>, (StatusCode, String)> {
+    let cursor: i64 = match after {
+        Some(after) => message_id_in_room(db, after, room_id).await?,
+        None => 0,
+    };
+
+    let messages = sqlx::query_as::<_, MessageRow>(&select_messages(
         r#"
-        SELECT
-            m.uuid,
-            u.username AS sender,
-            r.uuid AS room,
-            m.message_type,
-            m.content,
-            m.sent_at
-        FROM message_ m
-        JOIN user_ u ON u.id = m.sender
-        JOIN room_ r ON r.id = m.room
         WHERE m.room = $1
-        AND ($2::uuid IS NULL OR m.id < (SELECT id FROM message_ WHERE uuid = $2))
-        ORDER BY m.id DESC
+        AND m.id > $2
+        ORDER BY m.id ASC
         LIMIT $3
         "#,
-    )
+    ))
     .bind(room_id)
-    .bind(query.before)
+    .bind(cursor)
     .bind(limit)
-    .fetch_all(&db)
+    .fetch_all(db)
     .await
     .map_err(|e| {
         (
@@ -99,20 +390,22 @@ async fn list_messages(
         )
     })?;
 
-    let mut messages: Vec<Message> = messages
-        .into_iter()
-        .map(|m| Message {
-            uuid: m.uuid,
-            sender: m.sender,
-            message_type: m.message_type,
-            content: m.content,
-            sent_at: m.sent_at.format("%Y-%m-%d %H:%M:%S").to_string(),
-        })
-        .collect();
+    let mut messages: Vec<Message> = messages.into_iter().map(Message::from).collect();
+    load_reactions(db, viewer, &mut messages).await?;
 
-    messages.reverse();
+    Ok(messages)
+}
 
-    Ok(Json(messages))
+/// Uuid of the most recent message in a room, if any.
+pub async fn latest_message_uuid(
+    db: &PgPool,
+    room_id: i32,
+) -> Result<Option<Uuid>, (StatusCode, String)> {
+    sqlx::query_scalar("SELECT uuid FROM message_ WHERE room = $1 ORDER BY id DESC LIMIT 1")
+        .bind(room_id)
+        .fetch_optional(db)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "DB error".into()))
 }
 
 async fn create_message(
@@ -124,43 +417,402 @@ async fn create_message(
 ) -> Result<(StatusCode, Json<Message>), (StatusCode, String)> {
     let claims = verify_jwt(headers)?;
 
+    let user_id = user_id_from_uuid(&db, claims.sub).await?;
+
+    let message = persist_message(&db, &realtime, user_id, room_uuid, payload).await?;
+
+    Ok((StatusCode::CREATED, Json(message)))
+}
+
+/// Stores a new message and broadcasts it to the room's subscribers.
+/// Shared by the REST endpoint and the websocket protocol.
+pub async fn persist_message(
+    db: &PgPool,
+    realtime: &Realtime,
+    user_id: i32,
+    room_uuid: Uuid,
+    payload: NewMessagePayload,
+) -> Result<Message, (StatusCode, String)> {
+    let kind: MessageKind = payload.message_type.parse()?;
+    kind.validate(&payload.content)?;
+
+    let room_id = room_id_from_uuid(db, room_uuid).await?;
+
+    check_permission(db, user_id, room_id, Permission::Send).await?;
+
+    let parent_id = match (kind, payload.parent) {
+        (MessageKind::Reply, Some(parent)) => Some(reply_parent_id(db, parent, room_id).await?),
+        (MessageKind::Reply, None) => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "Replies must reference a parent message".into(),
+            ));
+        }
+        (_, Some(_)) => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "Only replies can reference a parent message".into(),
+            ));
+        }
+        (_, None) => None,
+    };
+
+    if kind == MessageKind::Attachment {
+        // Already checked to be a uuid by `validate`
+        let attachment_uuid = Uuid::parse_str(&payload.content)
+            .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid attachment uuid".into()))?;
+        check_attachment(db, attachment_uuid, room_id, user_id).await?;
+    }
+
+    let message_id: i64 = sqlx::query_scalar(
+        "INSERT INTO message_ (sender, room, message_type, content, uuid, parent)
+        VALUES ($1, $2, $3, $4, $5, $6) RETURNING id",
+    )
+    .bind(user_id)
+    .bind(room_id)
+    .bind(kind)
+    .bind(&payload.content)
+    .bind(Uuid::now_v7())
+    .bind(parent_id)
+    .fetch_one(db)
+    .await
+    .map_err(|_| (StatusCode::BAD_REQUEST, "Could not create message".into()))?;
+
+    let message = message_by_id(db, message_id).await?;
+
+    realtime.publish(room_id, Event::Message(Box::new(message.clone())));
+
+    if kind.is_editable() {
+        record_mentions(db, realtime, user_id, room_id, message_id, &message.content).await;
+    }
+
+    Ok(message)
+}
+
+/// Posts a message from the server into a room, on behalf of the user who caused it.
+pub async fn post_system_message(
+    db: &PgPool,
+    realtime: &Realtime,
+    user_id: i32,
+    room_id: i32,
+    content: String,
+) -> Result<Message, (StatusCode, String)> {
+    let message_id: i64 = sqlx::query_scalar(
+        "INSERT INTO message_ (sender, room, message_type, content, uuid)
+        VALUES ($1, $2, $3, $4, $5) RETURNING id",
+    )
+    .bind(user_id)
+    .bind(room_id)
+    .bind(MessageKind::System)
+    .bind(content)
+    .bind(Uuid::now_v7())
+    .fetch_one(db)
+    .await
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Could not create message".into(),
+        )
+    })?;
+
+    let message = message_by_id(db, message_id).await?;
+
+    realtime.publish(room_id, Event::Message(Box::new(message.clone())));
+
+    Ok(message)
+}
+
+/// Resolves the parent of a reply, which must be a live message of the same room.
+async fn reply_parent_id(
+    db: &PgPool,
+    parent_uuid: Uuid,
+    room_id: i32,
+) -> Result<i64, (StatusCode, String)> {
+    let (parent_id, parent_room, deleted): (i64, i32, bool) =
+        sqlx::query_as("SELECT id, room, deleted_at IS NOT NULL FROM message_ WHERE uuid = $1")
+            .bind(parent_uuid)
+            .fetch_optional(db)
+            .await
+            .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "DB error".into()))?
+            .ok_or((StatusCode::NOT_FOUND, "Parent message not found".into()))?;
+
+    if parent_room != room_id {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Replies must stay in the parent message's room".into(),
+        ));
+    }
+
+    if deleted {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Cannot reply to a deleted message".into(),
+        ));
+    }
+
+    Ok(parent_id)
+}
+
+async fn edit_message(
+    Path((room_uuid, message_uuid)): Path<(Uuid, Uuid)>,
+    Extension(db): Extension<PgPool>,
+    Extension(realtime): Extension<Realtime>,
+    headers: HeaderMap,
+    Json(payload): Json<EditMessagePayload>,
+) -> Result<Json<Message>, (StatusCode, String)> {
+    let claims = verify_jwt(headers)?;
+
     let user_id = user_id_from_uuid(&db, claims.sub).await?;
     let room_id = room_id_from_uuid(&db, room_uuid).await?;
 
-    if !is_member(user_id, room_id, &db).await {
+    check_permission(&db, user_id, room_id, Permission::Send).await?;
+
+    let mut tx = db
+        .begin()
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "DB error".into()))?;
+
+    let (message_id, sender_id, kind, old_content): (i64, i32, MessageKind, String) =
+        sqlx::query_as(
+            r#"
+        SELECT id, sender, message_type, content FROM message_
+        WHERE uuid = $1 AND room = $2 AND deleted_at IS NULL
+        FOR UPDATE
+        "#,
+        )
+        .bind(message_uuid)
+        .bind(room_id)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "DB error".into()))?
+        .ok_or((StatusCode::NOT_FOUND, "Message not found".into()))?;
+
+    if sender_id != user_id {
         return Err((
-            StatusCode::UNAUTHORIZED,
-            String::from("You are not a member of this room"),
+            StatusCode::FORBIDDEN,
+            "You can only edit your own messages".into(),
         ));
     }
 
-    let uuid = Uuid::now_v7();
+    if !kind.is_editable() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "This message cannot be edited".into(),
+        ));
+    }
 
-    let sent_at: chrono::NaiveDateTime = sqlx::query_scalar(
-        "INSERT INTO message_ (sender, room, message_type, content, uuid)
-        VALUES ($1, $2, $3, $4, $5) RETURNING sent_at",
+    kind.validate(&payload.content)?;
+
+    sqlx::query("INSERT INTO message_revision_ (message, content) VALUES ($1, $2)")
+        .bind(message_id)
+        .bind(&old_content)
+        .execute(&mut *tx)
+        .await
+        .map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Could not edit message".into(),
+            )
+        })?;
+
+    sqlx::query("UPDATE message_ SET content = $1, edited_at = now() WHERE id = $2")
+        .bind(&payload.content)
+        .bind(message_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Could not edit message".into(),
+            )
+        })?;
+
+    tx.commit().await.map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Could not edit message".into(),
+        )
+    })?;
+
+    let mut message = message_by_id(&db, message_id).await?;
+    load_reactions(&db, user_id, std::slice::from_mut(&mut message)).await?;
+
+    realtime.publish(
+        room_id,
+        Event::MessageEdited {
+            room: room_uuid,
+            uuid: message_uuid,
+            content: message.content.clone(),
+            edited_at: message.edited_at.clone().unwrap_or_default(),
+        },
+    );
+
+    // Only people newly mentioned by the edit are notified
+    record_mentions(
+        &db,
+        &realtime,
+        user_id,
+        room_id,
+        message_id,
+        &message.content,
+    )
+    .await;
+
+    Ok(Json(message))
+}
+
+/// Soft-deletes a message, leaving a tombstone so `before` cursors stay valid.
+/// Allowed for the sender and for whoever can delete messages of others.
+async fn delete_message(
+    Path((room_uuid, message_uuid)): Path<(Uuid, Uuid)>,
+    Extension(db): Extension<PgPool>,
+    Extension(realtime): Extension<Realtime>,
+    Extension(storage): Extension<Storage>,
+    headers: HeaderMap,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let claims = verify_jwt(headers)?;
+
+    let user_id = user_id_from_uuid(&db, claims.sub).await?;
+    let room_id = room_id_from_uuid(&db, room_uuid).await?;
+
+    let role = check_permission(&db, user_id, room_id, Permission::Read).await?;
+
+    let mut tx = db
+        .begin()
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "DB error".into()))?;
+
+    let (message_id, sender_id, message_type, content): (i64, i32, String, String) =
+        sqlx::query_as(
+            r#"
+        SELECT id, sender, message_type, content FROM message_
+        WHERE uuid = $1 AND room = $2 AND deleted_at IS NULL
+        FOR UPDATE
+        "#,
+        )
+        .bind(message_uuid)
+        .bind(room_id)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "DB error".into()))?
+        .ok_or((StatusCode::NOT_FOUND, "Message not found".into()))?;
+
+    if sender_id != user_id && !role.can(Permission::DeleteMessages) {
+        return Err((
+            StatusCode::FORBIDDEN,
+            "You cannot delete this message".into(),
+        ));
+    }
+
+    let deleted_at: chrono::NaiveDateTime = sqlx::query_scalar(
+        "UPDATE message_ SET deleted_at = now(), deleted_by = $1 WHERE id = $2 RETURNING deleted_at",
     )
     .bind(user_id)
-    .bind(room_id)
-    .bind(&payload.message_type)
-    .bind(&payload.content)
-    .bind(&uuid)
-    .fetch_one(&db)
+    .bind(message_id)
+    .fetch_one(&mut *tx)
     .await
-    .map_err(|_| (StatusCode::BAD_REQUEST, "Could not create message".into()))?;
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Could not delete message".into(),
+        )
+    })?;
 
-    let sender_name = username_from_uuid(&db, claims.sub).await?;
+    // The old contents go along with the message
+    sqlx::query("DELETE FROM message_revision_ WHERE message = $1")
+        .bind(message_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Could not delete message".into(),
+            )
+        })?;
 
-    let message = Message {
-        uuid: uuid,
-        sender: sender_name,
-        message_type: payload.message_type,
-        content: payload.content,
-        sent_at: sent_at.format("%Y-%m-%d %H:%M:%S").to_string(),
+    // The file goes too, unless another message still shows it
+    let attachment: Option<Uuid> = match message_type.parse()? {
+        MessageKind::Attachment => sqlx::query_scalar(
+            r#"
+            DELETE FROM attachment_
+            WHERE uuid = $1::uuid AND room = $2 AND NOT EXISTS (
+              SELECT 1 FROM message_
+              WHERE room = $2 AND message_type = 'attachment' AND content = $1 AND deleted_at IS NULL
+            )
+            RETURNING uuid
+            "#,
+        )
+        .bind(&content)
+        .bind(room_id)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Could not delete message".into(),
+            )
+        })?,
+        _ => None,
     };
 
-    let rt_sender = realtime.sender_for(room_id);
-    let _ = rt_sender.send(message.clone());
+    tx.commit().await.map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Could not delete message".into(),
+        )
+    })?;
+
+    if let Some(attachment) = attachment {
+        storage.remove(attachment).await;
+    }
+
+    realtime.publish(
+        room_id,
+        Event::MessageDeleted {
+            room: room_uuid,
+            uuid: message_uuid,
+            deleted_at: deleted_at.format(TIMESTAMP_FORMAT).to_string(),
+        },
+    );
 
-    Ok((StatusCode::CREATED, Json(message)))
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Previous contents of a message, oldest first.
+async fn list_revisions(
+    Path((room_uuid, message_uuid)): Path<(Uuid, Uuid)>,
+    Extension(db): Extension<PgPool>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<MessageRevision>>, (StatusCode, String)> {
+    let claims = verify_jwt(headers)?;
+
+    let user_id = user_id_from_uuid(&db, claims.sub).await?;
+    let room_id = room_id_from_uuid(&db, room_uuid).await?;
+
+    check_permission(&db, user_id, room_id, Permission::Read).await?;
+
+    let message_id = message_id_in_room(&db, message_uuid, room_id).await?;
+
+    let revisions = sqlx::query_as::<_, (String, chrono::NaiveDateTime)>(
+        "SELECT content, replaced_at FROM message_revision_ WHERE message = $1 ORDER BY id",
+    )
+    .bind(message_id)
+    .fetch_all(&db)
+    .await
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Could not list message history".into(),
+        )
+    })?;
+
+    Ok(Json(
+        revisions
+            .into_iter()
+            .map(|(content, replaced_at)| MessageRevision {
+                content,
+                replaced_at: replaced_at.format(TIMESTAMP_FORMAT).to_string(),
+            })
+            .collect(),
+    ))
 }
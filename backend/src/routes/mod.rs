@@ -1,5 +1,17 @@
+pub mod attachments;
+pub mod directory;
 pub mod friends;
+pub mod invite_links;
+pub mod mentions;
 pub mod messages;
+pub mod presence;
+pub mod reactions;
+pub mod receipts;
+pub mod roles;
+pub mod room_settings;
 pub mod rooms;
+pub mod search;
+pub mod sessions;
+pub mod totp;
 pub mod users;
 pub mod ws;
@@ -0,0 +1,263 @@
+use axum::{
+    Extension, Json, Router,
+    http::{HeaderMap, StatusCode},
+    routing::put,
+};
+use sqlx::{PgConnection, PgPool, Postgres, Transaction};
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::{
+    auth::verify_jwt,
+    db::user_id_from_uuid,
+    realtime::{Event, Realtime},
+};
+
+/// How often open sockets refresh their session
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Presence of user `u`: the status they picked while one of their sessions is open,
+/// offline otherwise.
+pub const PRESENCE: &str = r#"
+    CASE WHEN EXISTS (SELECT 1 FROM ws_session_ s WHERE s.user_id = u.id)
+    THEN u.status ELSE 'offline' END
+"#;
+
+/// Stored in `user_.status`, where `offline` lets a connected user appear offline
+#[derive(sqlx::Type, serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[sqlx(type_name = "varchar", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum Presence {
+    Online,
+    Away,
+    Offline,
+}
+
+#[derive(serde::Deserialize)]
+pub struct SetStatusPayload {
+    pub status: Presence,
+}
+
+pub fn routes() -> Router {
+    Router::new().route("/presence", put(set_status))
+}
+
+async fn presence_of(conn: &mut PgConnection, user_id: i32) -> Result<Presence, sqlx::Error> {
+    sqlx::query_scalar(&format!("SELECT {PRESENCE} FROM user_ u WHERE u.id = $1"))
+        .bind(user_id)
+        .fetch_one(conn)
+        .await
+}
+
+pub async fn user_presence(db: &PgPool, user_id: i32) -> Result<Presence, (StatusCode, String)> {
+    let mut conn = db
+        .acquire()
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "DB error".into()))?;
+
+    presence_of(&mut conn, user_id)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "DB error".into()))
+}
+
+/// Starts a change to the user's presence. Changes are serialized per user so that
+/// devices connecting or leaving at the same time each see the others' sessions.
+async fn begin_change(
+    db: &PgPool,
+    user_id: i32,
+) -> Result<(Transaction<'static, Postgres>, Presence), sqlx::Error> {
+    let mut tx = db.begin().await?;
+
+    sqlx::query("SELECT pg_advisory_xact_lock(hashtext('presence'), $1)")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+
+    let before = presence_of(&mut tx, user_id).await?;
+    Ok((tx, before))
+}
+
+/// Commits a presence change and tells the user's friends and devices if it shows.
+async fn finish_change(
+    mut tx: Transaction<'static, Postgres>,
+    realtime: &Realtime,
+    user_id: i32,
+    before: Presence,
+) -> Result<(), sqlx::Error> {
+    let after = presence_of(&mut tx, user_id).await?;
+
+    let (uuid, username): (Uuid, String) =
+        sqlx::query_as("SELECT uuid, username FROM user_ WHERE id = $1")
+            .bind(user_id)
+            .fetch_one(&mut *tx)
+            .await?;
+
+    let friends: Vec<i32> = sqlx::query_scalar(
+        r#"
+        SELECT CASE WHEN user_first = $1 THEN user_second ELSE user_first END
+        FROM friendship_
+        WHERE user_first = $1 OR user_second = $1
+        "#,
+    )
+    .bind(user_id)
+    .fetch_all(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    if after == before {
+        return Ok(());
+    }
+
+    let event = Event::Presence {
+        uuid,
+        user: username,
+        presence: after,
+    };
+
+    for friend in friends.into_iter().chain([user_id]) {
+        realtime.publish_to_user(friend, event.clone());
+    }
+
+    Ok(())
+}
+
+/// Registers a newly opened socket, returning its session id.
+pub async fn open_ws_session(db: &PgPool, realtime: &Realtime, user_id: i32) -> Option<Uuid> {
+    let session = Uuid::now_v7();
+
+    let result = async {
+        let (mut tx, before) = begin_change(db, user_id).await?;
+
+        // Left behind by instances that went away without closing them
+        sqlx::query(
+            "DELETE FROM ws_session_
+            WHERE user_id = $1 AND last_seen < now() - interval '90 seconds'",
+        )
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query("INSERT INTO ws_session_ (id, user_id) VALUES ($1, $2)")
+            .bind(session)
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+
+        finish_change(tx, realtime, user_id, before).await
+    }
+    .await;
+
+    match result {
+        Ok(()) => Some(session),
+        Err(e) => {
+            tracing::error!("could not open presence session: {e}");
+            None
+        }
+    }
+}
+
+pub async fn refresh_ws_session(db: &PgPool, session: Uuid) {
+    if let Err(e) = sqlx::query("UPDATE ws_session_ SET last_seen = now() WHERE id = $1")
+        .bind(session)
+        .execute(db)
+        .await
+    {
+        tracing::error!("could not refresh presence session: {e}");
+    }
+}
+
+pub async fn close_ws_session(db: &PgPool, realtime: &Realtime, user_id: i32, session: Uuid) {
+    let result = async {
+        let (mut tx, before) = begin_change(db, user_id).await?;
+
+        sqlx::query("DELETE FROM ws_session_ WHERE id = $1")
+            .bind(session)
+            .execute(&mut *tx)
+            .await?;
+
+        finish_change(tx, realtime, user_id, before).await
+    }
+    .await;
+
+    if let Err(e) = result {
+        tracing::error!("could not close presence session: {e}");
+    }
+}
+
+/// Closes sessions that missed three heartbeats, left behind by instances that went
+/// away, so the friends of their users see them go offline.
+pub async fn expire_stale_ws_sessions(db: PgPool, realtime: Realtime) {
+    let mut interval = tokio::time::interval(HEARTBEAT_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        let users: Vec<i32> = match sqlx::query_scalar(
+            "SELECT DISTINCT user_id FROM ws_session_ WHERE last_seen < now() - interval '90 seconds'",
+        )
+        .fetch_all(&db)
+        .await
+        {
+            Ok(users) => users,
+            Err(e) => {
+                tracing::error!("could not list stale presence sessions: {e}");
+                continue;
+            }
+        };
+
+        for user_id in users {
+            let result = async {
+                let (mut tx, before) = begin_change(&db, user_id).await?;
+
+                sqlx::query(
+                    "DELETE FROM ws_session_
+                    WHERE user_id = $1 AND last_seen < now() - interval '90 seconds'",
+                )
+                .bind(user_id)
+                .execute(&mut *tx)
+                .await?;
+
+                finish_change(tx, &realtime, user_id, before).await
+            }
+            .await;
+
+            if let Err(e) = result {
+                tracing::error!("could not expire presence sessions: {e}");
+            }
+        }
+    }
+}
+
+/// Sets the status shown while the user is connected.
+async fn set_status(
+    headers: HeaderMap,
+    Extension(db): Extension<PgPool>,
+    Extension(realtime): Extension<Realtime>,
+    Json(payload): Json<SetStatusPayload>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let claims = verify_jwt(headers)?;
+    let user_id = user_id_from_uuid(&db, claims.sub).await?;
+
+    let result = async {
+        let (mut tx, before) = begin_change(&db, user_id).await?;
+
+        sqlx::query("UPDATE user_ SET status = $1 WHERE id = $2")
+            .bind(payload.status)
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+
+        finish_change(tx, &realtime, user_id, before).await
+    }
+    .await;
+
+    result.map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Could not update status".into(),
+        )
+    })?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,229 @@
+use std::collections::HashMap;
+
+use axum::{
+    Extension, Router,
+    extract::Path,
+    http::{HeaderMap, StatusCode},
+    routing::put,
+};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    auth::verify_jwt,
+    db::{room_id_from_uuid, user_id_from_uuid, username_from_uuid},
+    realtime::{Event, Realtime},
+    routes::{
+        messages::Message,
+        roles::{Permission, check_permission},
+    },
+};
+
+/// Enough for flags, skin tones and joined sequences
+const MAX_EMOJI_LENGTH: usize = 16;
+
+/// Reactions to a message grouped by emoji, as seen by one user
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct Reaction {
+    pub emoji: String,
+    pub count: i64,
+    /// Whether the requesting user is one of the reactors
+    pub reacted: bool,
+}
+
+#[derive(sqlx::FromRow)]
+struct ReactionRow {
+    message: Uuid,
+    emoji: String,
+    count: i64,
+    reacted: bool,
+}
+
+pub fn routes() -> Router {
+    Router::new().route(
+        "/messages/{room_uuid}/{message_uuid}/reactions/{emoji}",
+        put(add_reaction).delete(remove_reaction),
+    )
+}
+
+fn validate_emoji(emoji: &str) -> Result<(), (StatusCode, String)> {
+    let valid = !emoji.is_empty()
+        && emoji.chars().count() <= MAX_EMOJI_LENGTH
+        && !emoji.is_ascii()
+        && !emoji.chars().any(|c| c.is_whitespace() || c.is_control());
+
+    if !valid {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Reactions must be a single short emoji".into(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Fills in the reactions of `messages` from `viewer`'s point of view.
+pub async fn load_reactions(
+    db: &PgPool,
+    viewer: i32,
+    messages: &mut [Message],
+) -> Result<(), (StatusCode, String)> {
+    if messages.is_empty() {
+        return Ok(());
+    }
+
+    let uuids: Vec<Uuid> = messages.iter().map(|m| m.uuid).collect();
+
+    let rows = sqlx::query_as::<_, ReactionRow>(
+        r#"
+        SELECT m.uuid AS message, r.emoji, count(*) AS count, bool_or(r.user_id = $2) AS reacted
+        FROM reaction_ r
+        JOIN message_ m ON m.id = r.message
+        WHERE m.uuid = ANY($1) AND m.deleted_at IS NULL
+        GROUP BY m.uuid, r.emoji
+        ORDER BY min(r.reacted_at)
+        "#,
+    )
+    .bind(&uuids)
+    .bind(viewer)
+    .fetch_all(db)
+    .await
+    .map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to list reactions: {e}"),
+        )
+    })?;
+
+    let mut by_message: HashMap<Uuid, Vec<Reaction>> = HashMap::new();
+    for row in rows {
+        by_message.entry(row.message).or_default().push(Reaction {
+            emoji: row.emoji,
+            count: row.count,
+            reacted: row.reacted,
+        });
+    }
+
+    for message in messages {
+        message.reactions = by_message.remove(&message.uuid).unwrap_or_default();
+    }
+
+    Ok(())
+}
+
+/// Resolves the caller and a live message of a room they belong to.
+async fn reaction_target(
+    db: &PgPool,
+    headers: HeaderMap,
+    room_uuid: Uuid,
+    message_uuid: Uuid,
+) -> Result<(i32, String, i32, i64), (StatusCode, String)> {
+    let claims = verify_jwt(headers)?;
+
+    let user_id = user_id_from_uuid(db, claims.sub).await?;
+    let room_id = room_id_from_uuid(db, room_uuid).await?;
+
+    check_permission(db, user_id, room_id, Permission::Send).await?;
+
+    let message_id: i64 = sqlx::query_scalar(
+        "SELECT id FROM message_ WHERE uuid = $1 AND room = $2 AND deleted_at IS NULL",
+    )
+    .bind(message_uuid)
+    .bind(room_id)
+    .fetch_optional(db)
+    .await
+    .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "DB error".into()))?
+    .ok_or((StatusCode::NOT_FOUND, "Message not found".into()))?;
+
+    let username = username_from_uuid(db, claims.sub).await?;
+
+    Ok((user_id, username, room_id, message_id))
+}
+
+async fn add_reaction(
+    Path((room_uuid, message_uuid, emoji)): Path<(Uuid, Uuid, String)>,
+    Extension(db): Extension<PgPool>,
+    Extension(realtime): Extension<Realtime>,
+    headers: HeaderMap,
+) -> Result<StatusCode, (StatusCode, String)> {
+    validate_emoji(&emoji)?;
+
+    let (user_id, username, room_id, message_id) =
+        reaction_target(&db, headers, room_uuid, message_uuid).await?;
+
+    let added = sqlx::query(
+        "INSERT INTO reaction_ (message, user_id, emoji) VALUES ($1, $2, $3)
+        ON CONFLICT DO NOTHING",
+    )
+    .bind(message_id)
+    .bind(user_id)
+    .bind(&emoji)
+    .execute(&db)
+    .await
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Could not add reaction".into(),
+        )
+    })?
+    .rows_affected()
+        > 0;
+
+    if !added {
+        return Ok(StatusCode::OK);
+    }
+
+    realtime.publish(
+        room_id,
+        Event::ReactionAdded {
+            room: room_uuid,
+            uuid: message_uuid,
+            emoji,
+            user: username,
+        },
+    );
+
+    Ok(StatusCode::CREATED)
+}
+
+async fn remove_reaction(
+    Path((room_uuid, message_uuid, emoji)): Path<(Uuid, Uuid, String)>,
+    Extension(db): Extension<PgPool>,
+    Extension(realtime): Extension<Realtime>,
+    headers: HeaderMap,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let (user_id, username, room_id, message_id) =
+        reaction_target(&db, headers, room_uuid, message_uuid).await?;
+
+    let removed =
+        sqlx::query("DELETE FROM reaction_ WHERE message = $1 AND user_id = $2 AND emoji = $3")
+            .bind(message_id)
+            .bind(user_id)
+            .bind(&emoji)
+            .execute(&db)
+            .await
+            .map_err(|_| {
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Could not remove reaction".into(),
+                )
+            })?
+            .rows_affected()
+            > 0;
+
+    if !removed {
+        return Err((StatusCode::NOT_FOUND, "Reaction not found".into()));
+    }
+
+    realtime.publish(
+        room_id,
+        Event::ReactionRemoved {
+            room: room_uuid,
+            uuid: message_uuid,
+            emoji,
+            user: username,
+        },
+    );
+
+    Ok(StatusCode::NO_CONTENT)
+}
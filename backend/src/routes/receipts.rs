@@ -0,0 +1,150 @@
+use axum::{
+    Extension, Json, Router,
+    extract::Path,
+    http::{HeaderMap, StatusCode},
+    routing::{get, post},
+};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    auth::verify_jwt,
+    db::{room_id_from_uuid, user_id_from_uuid, username_from_id},
+    realtime::{Event, Realtime},
+    routes::{
+        mentions::mark_mentions_read,
+        messages::TIMESTAMP_FORMAT,
+        roles::{Permission, check_permission},
+    },
+};
+
+/// How far a member has read in a room
+#[derive(serde::Serialize)]
+pub struct ReadReceipt {
+    pub user: String,
+    /// Last message read
+    pub uuid: Uuid,
+    pub read_at: String,
+}
+
+#[derive(sqlx::FromRow)]
+struct ReadReceiptRow {
+    user: String,
+    uuid: Uuid,
+    read_at: chrono::NaiveDateTime,
+}
+
+pub fn routes() -> Router {
+    Router::new()
+        .route("/messages/{room_uuid}/receipts", get(list_receipts))
+        .route("/messages/{room_uuid}/{message_uuid}/read", post(mark_read))
+}
+
+/// Moves the caller's read cursor up to a message. Cursors never go backwards,
+/// so marking an older message is a no-op.
+async fn mark_read(
+    Path((room_uuid, message_uuid)): Path<(Uuid, Uuid)>,
+    Extension(db): Extension<PgPool>,
+    Extension(realtime): Extension<Realtime>,
+    headers: HeaderMap,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let claims = verify_jwt(headers)?;
+
+    let user_id = user_id_from_uuid(&db, claims.sub).await?;
+    let room_id = room_id_from_uuid(&db, room_uuid).await?;
+
+    check_permission(&db, user_id, room_id, Permission::Read).await?;
+
+    let message_id: i64 =
+        sqlx::query_scalar("SELECT id FROM message_ WHERE uuid = $1 AND room = $2")
+            .bind(message_uuid)
+            .bind(room_id)
+            .fetch_optional(&db)
+            .await
+            .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "DB error".into()))?
+            .ok_or((StatusCode::NOT_FOUND, "Message not found".into()))?;
+
+    let read_at: Option<chrono::NaiveDateTime> = sqlx::query_scalar(
+        r#"
+        INSERT INTO read_cursor_ (user_id, room, message)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (user_id, room) DO UPDATE
+            SET message = EXCLUDED.message, read_at = now()
+            WHERE read_cursor_.message < EXCLUDED.message
+        RETURNING read_at
+        "#,
+    )
+    .bind(user_id)
+    .bind(room_id)
+    .bind(message_id)
+    .fetch_optional(&db)
+    .await
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Could not update read cursor".into(),
+        )
+    })?;
+
+    // Also catches up on mentions added later by edits of older messages
+    mark_mentions_read(&db, user_id, room_id, message_id).await?;
+
+    if let Some(read_at) = read_at {
+        realtime.publish(
+            room_id,
+            Event::MessagesRead {
+                room: room_uuid,
+                user: username_from_id(&db, user_id).await?,
+                uuid: message_uuid,
+                read_at: read_at.format(TIMESTAMP_FORMAT).to_string(),
+            },
+        );
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Every read cursor of a room, to show "seen by" indicators.
+async fn list_receipts(
+    Path(room_uuid): Path<Uuid>,
+    Extension(db): Extension<PgPool>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<ReadReceipt>>, (StatusCode, String)> {
+    let claims = verify_jwt(headers)?;
+
+    let user_id = user_id_from_uuid(&db, claims.sub).await?;
+    let room_id = room_id_from_uuid(&db, room_uuid).await?;
+
+    check_permission(&db, user_id, room_id, Permission::Read).await?;
+
+    let receipts = sqlx::query_as::<_, ReadReceiptRow>(
+        r#"
+        SELECT u.username AS user, m.uuid, c.read_at
+        FROM read_cursor_ c
+        JOIN user_ u ON u.id = c.user_id
+        JOIN message_ m ON m.id = c.message
+        WHERE c.room = $1
+        ORDER BY c.message DESC
+        "#,
+    )
+    .bind(room_id)
+    .fetch_all(&db)
+    .await
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Could not list read receipts".into(),
+        )
+    })?;
+
+    Ok(Json(
+        receipts
+            .into_iter()
+            .map(|r| ReadReceipt {
+                user: r.user,
+                uuid: r.uuid,
+                read_at: r.read_at.format(TIMESTAMP_FORMAT).to_string(),
+            })
+            .collect(),
+    ))
+}
@@ -0,0 +1,270 @@
+use axum::{
+    Extension, Json, Router,
+    extract::Path,
+    http::{HeaderMap, StatusCode},
+    routing::put,
+};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    auth::verify_jwt,
+    db::{find_user_by_uuid, room_id_from_uuid, user_id_from_uuid, username_from_id},
+    realtime::{Event, Realtime},
+};
+
+/// Role of user `$1` in room `r`. The owner is `room_.owner`, everyone else
+/// gets the role of their membership, or `member` in global rooms.
+pub const ROLE: &str = r#"
+    CASE WHEN r.owner = $1 THEN 'owner' ELSE COALESCE(
+        (SELECT m.role FROM membership_ m WHERE m.user_id = $1 AND m.room = r.id),
+        'member'
+    ) END
+"#;
+
+/// Ordered from least to most trusted, each role can do everything the previous ones can
+#[derive(
+    sqlx::Type,
+    serde::Serialize,
+    serde::Deserialize,
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+)]
+#[sqlx(type_name = "varchar", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Member,
+    Moderator,
+    Admin,
+    Owner,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Permission {
+    Read,
+    Send,
+    Invite,
+    Kick,
+    /// Deleting messages sent by others, anyone can delete their own
+    DeleteMessages,
+    /// Changing the name, description, topic and avatar
+    EditRoom,
+    /// Promoting and demoting members ranked below oneself
+    ChangeRoles,
+    /// Creating, listing and revoking invite links
+    ManageInviteLinks,
+    TransferOwnership,
+    DeleteRoom,
+}
+
+impl Permission {
+    fn minimum_role(self) -> Role {
+        match self {
+            Permission::Read | Permission::Send => Role::Member,
+            Permission::Invite | Permission::Kick | Permission::DeleteMessages => Role::Moderator,
+            Permission::EditRoom | Permission::ChangeRoles | Permission::ManageInviteLinks => {
+                Role::Admin
+            }
+            Permission::TransferOwnership | Permission::DeleteRoom => Role::Owner,
+        }
+    }
+
+    fn description(self) -> &'static str {
+        match self {
+            Permission::Read => "read this room",
+            Permission::Send => "send messages in this room",
+            Permission::Invite => "invite people to this room",
+            Permission::Kick => "kick members of this room",
+            Permission::DeleteMessages => "delete messages of others in this room",
+            Permission::EditRoom => "change the settings of this room",
+            Permission::ChangeRoles => "change roles in this room",
+            Permission::ManageInviteLinks => "manage the invite links of this room",
+            Permission::TransferOwnership => "transfer the ownership of this room",
+            Permission::DeleteRoom => "delete this room",
+        }
+    }
+}
+
+impl Role {
+    pub fn can(self, permission: Permission) -> bool {
+        self >= permission.minimum_role()
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct SetRolePayload {
+    pub role: Role,
+}
+
+pub fn routes() -> Router {
+    Router::new().route("/rooms/{room_id}/members/{user_uuid}/role", put(set_role))
+}
+
+/// Role of a user in a room, `None` when they are not a member.
+pub async fn room_role(
+    db: &PgPool,
+    user_id: i32,
+    room_id: i32,
+) -> Result<Option<Role>, (StatusCode, String)> {
+    sqlx::query_scalar(&format!(
+        r#"
+        SELECT {ROLE}
+        FROM room_ r
+        WHERE r.id = $2 AND (r.global OR EXISTS (
+            SELECT 1
+            FROM membership_ m
+            WHERE m.user_id = $1 AND m.room = r.id
+        ))
+        "#
+    ))
+    .bind(user_id)
+    .bind(room_id)
+    .fetch_optional(db)
+    .await
+    .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "DB error".into()))
+}
+
+/// The one check guarding room access, returning the caller's role when allowed.
+pub async fn check_permission(
+    db: &PgPool,
+    user_id: i32,
+    room_id: i32,
+    permission: Permission,
+) -> Result<Role, (StatusCode, String)> {
+    let role = room_role(db, user_id, room_id).await?.ok_or((
+        StatusCode::FORBIDDEN,
+        String::from("You are not a member of this room"),
+    ))?;
+
+    if !role.can(permission) {
+        return Err((
+            StatusCode::FORBIDDEN,
+            format!("You are not allowed to {}", permission.description()),
+        ));
+    }
+
+    Ok(role)
+}
+
+/// Changes the role of a member. Both their current and new role must rank below
+/// the caller's, ownership is transferred separately.
+async fn set_role(
+    Path((room_uuid, member_uuid)): Path<(Uuid, Uuid)>,
+    headers: HeaderMap,
+    Extension(db): Extension<PgPool>,
+    Extension(realtime): Extension<Realtime>,
+    Json(payload): Json<SetRolePayload>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let claims = verify_jwt(headers)?;
+
+    let user_id = user_id_from_uuid(&db, claims.sub).await?;
+    let room_id = room_id_from_uuid(&db, room_uuid).await?;
+    let member_id = find_user_by_uuid(&db, member_uuid).await?;
+
+    let role = check_permission(&db, user_id, room_id, Permission::ChangeRoles).await?;
+
+    let member_role = room_role(&db, member_id, room_id).await?.ok_or((
+        StatusCode::NOT_FOUND,
+        "This user is not a member of this room".into(),
+    ))?;
+
+    if payload.role == Role::Owner {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Ownership cannot be given through roles".into(),
+        ));
+    }
+
+    if member_role >= role || payload.role >= role {
+        return Err((
+            StatusCode::FORBIDDEN,
+            "You can only change roles below your own".into(),
+        ));
+    }
+
+    // Global rooms have no membership row until someone is given a role
+    sqlx::query(
+        r#"
+        INSERT INTO membership_ (user_id, room, role)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (user_id, room) DO UPDATE SET role = EXCLUDED.role
+        "#,
+    )
+    .bind(member_id)
+    .bind(room_id)
+    .bind(payload.role)
+    .execute(&db)
+    .await
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Could not change role".into(),
+        )
+    })?;
+
+    if member_role != payload.role {
+        realtime.publish(
+            room_id,
+            Event::RoleChanged {
+                room: room_uuid,
+                user: username_from_id(&db, member_id).await?,
+                role: payload.role,
+            },
+        );
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn members_can_only_read_and_send() {
+        assert!(Role::Member.can(Permission::Read));
+        assert!(Role::Member.can(Permission::Send));
+        assert!(!Role::Member.can(Permission::Invite));
+        assert!(!Role::Member.can(Permission::DeleteMessages));
+    }
+
+    #[test]
+    fn moderators_can_invite_kick_and_delete_messages() {
+        assert!(Role::Moderator.can(Permission::Invite));
+        assert!(Role::Moderator.can(Permission::Kick));
+        assert!(Role::Moderator.can(Permission::DeleteMessages));
+        assert!(!Role::Moderator.can(Permission::ChangeRoles));
+    }
+
+    #[test]
+    fn admins_and_owners_can_change_roles() {
+        assert!(Role::Admin.can(Permission::ChangeRoles));
+        assert!(Role::Owner.can(Permission::ChangeRoles));
+        assert!(Role::Owner.can(Permission::Kick));
+    }
+
+    #[test]
+    fn admins_edit_room_settings() {
+        assert!(Role::Admin.can(Permission::EditRoom));
+        assert!(!Role::Moderator.can(Permission::EditRoom));
+    }
+
+    #[test]
+    fn only_owners_can_transfer_or_delete_rooms() {
+        assert!(Role::Owner.can(Permission::TransferOwnership));
+        assert!(Role::Owner.can(Permission::DeleteRoom));
+        assert!(!Role::Admin.can(Permission::TransferOwnership));
+        assert!(!Role::Admin.can(Permission::DeleteRoom));
+    }
+
+    #[test]
+    fn admins_manage_invite_links() {
+        assert!(Role::Admin.can(Permission::ManageInviteLinks));
+        assert!(!Role::Moderator.can(Permission::ManageInviteLinks));
+    }
+}
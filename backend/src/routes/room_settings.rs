@@ -0,0 +1,533 @@
+use axum::{
+    Extension, Json, Router,
+    extract::{DefaultBodyLimit, Multipart, Path},
+    http::{HeaderMap, StatusCode, header},
+    response::IntoResponse,
+    routing::{get, patch, post},
+};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    auth::verify_jwt,
+    db::{find_user_by_uuid, room_id_from_uuid, user_id_from_uuid, username_from_id},
+    realtime::{Event, Realtime},
+    routes::{
+        attachments::read_file_field,
+        messages::post_system_message,
+        roles::{Permission, Role, check_permission, room_role},
+        rooms::{
+            MAX_DESCRIPTION_LENGTH, MAX_TOPIC_LENGTH, Room, check_length, room_for, validate_name,
+        },
+    },
+    storage::{self, Storage},
+};
+
+/// Fields left out are not changed
+#[derive(serde::Deserialize)]
+pub struct UpdateRoomPayload {
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub topic: Option<String>,
+    /// Lists the room in the directory, or takes it out
+    pub public: Option<bool>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct TransferRoomPayload {
+    pub user_uuid: Uuid,
+}
+
+#[derive(sqlx::FromRow)]
+struct RoomSettings {
+    name: String,
+    description: String,
+    topic: String,
+    avatar: Option<Uuid>,
+    global: bool,
+    public: bool,
+}
+
+pub fn routes() -> Router {
+    Router::new()
+        .route("/rooms/{room_id}", patch(update_room).delete(delete_room))
+        .route(
+            "/rooms/{room_id}/avatar",
+            get(get_avatar)
+                // The size limit is enforced while streaming the file
+                .put(set_avatar)
+                .layer(DefaultBodyLimit::disable())
+                .delete(remove_avatar),
+        )
+        .route("/rooms/{room_id}/transfer", post(transfer_room))
+}
+
+/// Resolves the caller and room, checking the caller may do `permission`
+/// on a room that isn't direct messages.
+async fn settings_target(
+    db: &PgPool,
+    headers: HeaderMap,
+    room_uuid: Uuid,
+    permission: Permission,
+) -> Result<(i32, i32), (StatusCode, String)> {
+    let claims = verify_jwt(headers)?;
+
+    let user_id = user_id_from_uuid(db, claims.sub).await?;
+    let room_id = room_id_from_uuid(db, room_uuid).await?;
+
+    check_permission(db, user_id, room_id, permission).await?;
+
+    let direct: bool = sqlx::query_scalar("SELECT direct FROM room_ WHERE id = $1")
+        .bind(room_id)
+        .fetch_one(db)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "DB error".into()))?;
+
+    if direct {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Direct messages have no settings".into(),
+        ));
+    }
+
+    Ok((user_id, room_id))
+}
+
+async fn room_settings(db: &PgPool, room_id: i32) -> Result<RoomSettings, (StatusCode, String)> {
+    sqlx::query_as(
+        "SELECT name, description, topic, avatar, global, public FROM room_ WHERE id = $1",
+    )
+    .bind(room_id)
+    .fetch_one(db)
+    .await
+    .map_err(|_| (StatusCode::NOT_FOUND, "Room not found".into()))
+}
+
+/// Tells subscribers about the current settings of a room.
+async fn publish_room_updated(
+    db: &PgPool,
+    realtime: &Realtime,
+    room_uuid: Uuid,
+    room_id: i32,
+) -> Result<(), (StatusCode, String)> {
+    let settings = room_settings(db, room_id).await?;
+
+    realtime.publish(
+        room_id,
+        Event::RoomUpdated {
+            room: room_uuid,
+            name: settings.name,
+            description: settings.description,
+            topic: settings.topic,
+            avatar: settings.avatar,
+            public: settings.public,
+        },
+    );
+
+    Ok(())
+}
+
+/// Changes the name, description, topic or visibility of a room, with a system message
+/// for each of them that actually changed.
+async fn update_room(
+    Path(room_uuid): Path<Uuid>,
+    headers: HeaderMap,
+    Extension(db): Extension<PgPool>,
+    Extension(realtime): Extension<Realtime>,
+    Json(payload): Json<UpdateRoomPayload>,
+) -> Result<Json<Room>, (StatusCode, String)> {
+    let (user_id, room_id) = settings_target(&db, headers, room_uuid, Permission::EditRoom).await?;
+
+    let name = payload.name.as_deref().map(validate_name).transpose()?;
+    if let Some(description) = &payload.description {
+        check_length("Room descriptions", description, MAX_DESCRIPTION_LENGTH)?;
+    }
+    if let Some(topic) = &payload.topic {
+        check_length("Room topics", topic, MAX_TOPIC_LENGTH)?;
+    }
+
+    let current = room_settings(&db, room_id).await?;
+    let username = username_from_id(&db, user_id).await?;
+
+    let mut announcements = Vec::new();
+
+    if let Some(name) = &name
+        && *name != current.name
+    {
+        announcements.push(format!("{username} renamed the room to \"{name}\""));
+    }
+
+    if let Some(description) = &payload.description
+        && *description != current.description
+    {
+        announcements.push(if description.is_empty() {
+            format!("{username} removed the room description")
+        } else {
+            format!("{username} changed the room description")
+        });
+    }
+
+    if let Some(topic) = &payload.topic
+        && *topic != current.topic
+    {
+        announcements.push(if topic.is_empty() {
+            format!("{username} cleared the topic")
+        } else {
+            format!("{username} set the topic to \"{topic}\"")
+        });
+    }
+
+    if let Some(public) = payload.public
+        && public != current.public
+    {
+        if current.global {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "Global rooms are already open to everyone".into(),
+            ));
+        }
+
+        announcements.push(if public {
+            format!("{username} listed the room in the directory")
+        } else {
+            format!("{username} made the room invite only")
+        });
+    }
+
+    if !announcements.is_empty() {
+        sqlx::query(
+            r#"
+            UPDATE room_ SET
+                name = COALESCE($1, name),
+                description = COALESCE($2, description),
+                topic = COALESCE($3, topic),
+                public = COALESCE($4, public)
+            WHERE id = $5
+            "#,
+        )
+        .bind(&name)
+        .bind(&payload.description)
+        .bind(&payload.topic)
+        .bind(payload.public)
+        .bind(room_id)
+        .execute(&db)
+        .await
+        .map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Could not update room".into(),
+            )
+        })?;
+
+        for announcement in announcements {
+            post_system_message(&db, &realtime, user_id, room_id, announcement).await?;
+        }
+
+        publish_room_updated(&db, &realtime, room_uuid, room_id).await?;
+    }
+
+    Ok(Json(room_for(&db, user_id, room_id).await?))
+}
+
+async fn get_avatar(
+    Path(room_uuid): Path<Uuid>,
+    headers: HeaderMap,
+    Extension(db): Extension<PgPool>,
+    Extension(storage): Extension<Storage>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let claims = verify_jwt(headers)?;
+
+    let user_id = user_id_from_uuid(&db, claims.sub).await?;
+    let room_id = room_id_from_uuid(&db, room_uuid).await?;
+
+    check_permission(&db, user_id, room_id, Permission::Read).await?;
+
+    let avatar = room_settings(&db, room_id)
+        .await?
+        .avatar
+        .ok_or((StatusCode::NOT_FOUND, "This room has no avatar".into()))?;
+
+    let data = storage
+        .load(avatar)
+        .await
+        .map_err(|_| (StatusCode::NOT_FOUND, "This room has no avatar".into()))?;
+
+    Ok(([(header::CONTENT_TYPE, "image/png")], data))
+}
+
+/// Replaces the avatar with the `file` field of a multipart form,
+/// stored scaled down to a PNG like attachment thumbnails.
+async fn set_avatar(
+    Path(room_uuid): Path<Uuid>,
+    headers: HeaderMap,
+    Extension(db): Extension<PgPool>,
+    Extension(realtime): Extension<Realtime>,
+    Extension(storage): Extension<Storage>,
+    mut multipart: Multipart,
+) -> Result<Json<Room>, (StatusCode, String)> {
+    let (user_id, room_id) = settings_target(&db, headers, room_uuid, Permission::EditRoom).await?;
+
+    let (_, mime_type, data) =
+        read_file_field(&mut multipart, storage.max_size, storage::is_thumbnailable).await?;
+
+    let image = tokio::task::spawn_blocking(move || storage::thumbnail(&data, &mime_type))
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Upload failed".into()))?
+        .ok_or((
+            StatusCode::BAD_REQUEST,
+            "The file is not a valid image".into(),
+        ))?;
+
+    let avatar = Uuid::now_v7();
+
+    if let Err(e) = storage.save(avatar, &image).await {
+        tracing::error!("failed to store avatar {avatar}: {e}");
+        storage.remove(avatar).await;
+        return Err((StatusCode::INTERNAL_SERVER_ERROR, "Upload failed".into()));
+    }
+
+    let previous = replace_avatar(&db, room_id, Some(avatar)).await;
+
+    let previous = match previous {
+        Ok(previous) => previous,
+        Err(e) => {
+            storage.remove(avatar).await;
+            return Err(e);
+        }
+    };
+
+    if let Some(previous) = previous {
+        storage.remove(previous).await;
+    }
+
+    let username = username_from_id(&db, user_id).await?;
+    post_system_message(
+        &db,
+        &realtime,
+        user_id,
+        room_id,
+        format!("{username} changed the room avatar"),
+    )
+    .await?;
+    publish_room_updated(&db, &realtime, room_uuid, room_id).await?;
+
+    Ok(Json(room_for(&db, user_id, room_id).await?))
+}
+
+async fn remove_avatar(
+    Path(room_uuid): Path<Uuid>,
+    headers: HeaderMap,
+    Extension(db): Extension<PgPool>,
+    Extension(realtime): Extension<Realtime>,
+    Extension(storage): Extension<Storage>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let (user_id, room_id) = settings_target(&db, headers, room_uuid, Permission::EditRoom).await?;
+
+    let previous = replace_avatar(&db, room_id, None)
+        .await?
+        .ok_or((StatusCode::NOT_FOUND, "This room has no avatar".into()))?;
+
+    storage.remove(previous).await;
+
+    let username = username_from_id(&db, user_id).await?;
+    post_system_message(
+        &db,
+        &realtime,
+        user_id,
+        room_id,
+        format!("{username} removed the room avatar"),
+    )
+    .await?;
+    publish_room_updated(&db, &realtime, room_uuid, room_id).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Sets the avatar of a room, returning the one it replaces.
+async fn replace_avatar(
+    db: &PgPool,
+    room_id: i32,
+    avatar: Option<Uuid>,
+) -> Result<Option<Uuid>, (StatusCode, String)> {
+    sqlx::query_scalar(
+        r#"
+        UPDATE room_ r SET avatar = $1
+        FROM (SELECT id, avatar FROM room_ WHERE id = $2 FOR UPDATE) previous
+        WHERE r.id = previous.id
+        RETURNING previous.avatar
+        "#,
+    )
+    .bind(avatar)
+    .bind(room_id)
+    .fetch_one(db)
+    .await
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Could not update room avatar".into(),
+        )
+    })
+}
+
+/// Hands the room over to another member, the previous owner staying on as an admin.
+async fn transfer_room(
+    Path(room_uuid): Path<Uuid>,
+    headers: HeaderMap,
+    Extension(db): Extension<PgPool>,
+    Extension(realtime): Extension<Realtime>,
+    Json(payload): Json<TransferRoomPayload>,
+) -> Result<Json<Room>, (StatusCode, String)> {
+    let (user_id, room_id) =
+        settings_target(&db, headers, room_uuid, Permission::TransferOwnership).await?;
+    let new_owner_id = find_user_by_uuid(&db, payload.user_uuid).await?;
+
+    if new_owner_id == user_id {
+        return Err((StatusCode::BAD_REQUEST, "You already own this room".into()));
+    }
+
+    if room_role(&db, new_owner_id, room_id).await?.is_none() {
+        return Err((
+            StatusCode::NOT_FOUND,
+            "This user is not a member of this room".into(),
+        ));
+    }
+
+    let mut tx = db
+        .begin()
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "DB error".into()))?;
+
+    let rows = sqlx::query("UPDATE room_ SET owner = $1 WHERE id = $2 AND owner = $3")
+        .bind(new_owner_id)
+        .bind(room_id)
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Could not transfer room".into(),
+            )
+        })?
+        .rows_affected();
+
+    if rows == 0 {
+        return Err((
+            StatusCode::CONFLICT,
+            "The ownership of this room has already changed".into(),
+        ));
+    }
+
+    sqlx::query(
+        r#"
+        INSERT INTO membership_ (user_id, room, role)
+        VALUES ($1, $2, 'admin')
+        ON CONFLICT (user_id, room) DO UPDATE SET role = EXCLUDED.role
+        "#,
+    )
+    .bind(user_id)
+    .bind(room_id)
+    .execute(&mut *tx)
+    .await
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Could not transfer room".into(),
+        )
+    })?;
+
+    tx.commit().await.map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Could not transfer room".into(),
+        )
+    })?;
+
+    let username = username_from_id(&db, user_id).await?;
+    let new_owner = username_from_id(&db, new_owner_id).await?;
+
+    post_system_message(
+        &db,
+        &realtime,
+        user_id,
+        room_id,
+        format!("{username} transferred the ownership of the room to {new_owner}"),
+    )
+    .await?;
+
+    for (user, role) in [(new_owner, Role::Owner), (username, Role::Admin)] {
+        realtime.publish(
+            room_id,
+            Event::RoleChanged {
+                room: room_uuid,
+                user,
+                role,
+            },
+        );
+    }
+
+    Ok(Json(room_for(&db, user_id, room_id).await?))
+}
+
+/// Deletes a room with its messages, attachments and invites.
+async fn delete_room(
+    Path(room_uuid): Path<Uuid>,
+    headers: HeaderMap,
+    Extension(db): Extension<PgPool>,
+    Extension(realtime): Extension<Realtime>,
+    Extension(storage): Extension<Storage>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let (_, room_id) = settings_target(&db, headers, room_uuid, Permission::DeleteRoom).await?;
+
+    let mut tx = db
+        .begin()
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "DB error".into()))?;
+
+    // Locking the room holds off uploads until it is gone
+    sqlx::query("SELECT 1 FROM room_ WHERE id = $1 FOR UPDATE")
+        .bind(room_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "DB error".into()))?;
+
+    // Files outlive their rows, remember them to clean up afterwards
+    let files: Vec<Uuid> = sqlx::query_scalar(
+        r#"
+        SELECT uuid FROM attachment_ WHERE room = $1
+        UNION ALL
+        SELECT avatar FROM room_ WHERE id = $1 AND avatar IS NOT NULL
+        "#,
+    )
+    .bind(room_id)
+    .fetch_all(&mut *tx)
+    .await
+    .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "DB error".into()))?;
+
+    sqlx::query("DELETE FROM room_ WHERE id = $1")
+        .bind(room_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| {
+            tracing::error!("could not delete room {room_uuid}: {e}");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Could not delete room".into(),
+            )
+        })?;
+
+    tx.commit().await.map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Could not delete room".into(),
+        )
+    })?;
+
+    realtime.publish(room_id, Event::RoomDeleted { room: room_uuid });
+
+    for file in files {
+        storage.remove(file).await;
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
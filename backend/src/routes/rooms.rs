@@ -2,27 +2,63 @@ use axum::{
     Extension, Json, Router,
     extract::Path,
     http::{HeaderMap, StatusCode},
-    routing::{get, post},
+    routing::{delete, get, post},
 };
 use sqlx::{PgPool, Pool, Postgres};
 use uuid::Uuid;
 
-use crate::db::{id_from_username, room_name_from_uuid, user_id_from_uuid, username_from_id};
+use crate::db::{
+    find_user_by_uuid, id_from_username, room_name_from_uuid, user_id_from_uuid, username_from_id,
+};
+use crate::realtime::{Event, Realtime};
+use crate::routes::presence::{PRESENCE, Presence};
+use crate::routes::roles::{Permission, ROLE, Role, check_permission, room_role};
 use crate::{auth::verify_jwt, db::room_id_from_uuid};
 
 #[derive(sqlx::FromRow, serde::Serialize)]
 pub struct Room {
     pub uuid: Uuid,
     pub name: String,
+    pub description: String,
+    pub topic: String,
+    /// Served by `/rooms/{uuid}/avatar`, changes with every new avatar
+    pub avatar: Option<Uuid>,
     pub global: bool,
+    /// Listed in the room directory
+    pub public: bool,
+    /// Direct message room, named after the other participant
+    pub direct: bool,
     pub owner_name: String,
     pub owner_uuid: Uuid,
+    /// Messages from others past the caller's read cursor
+    pub unread: i64,
+    /// The caller's role
+    pub role: Role,
+}
+
+#[derive(sqlx::FromRow, serde::Serialize)]
+pub struct RoomMember {
+    pub uuid: Uuid,
+    pub username: String,
+    pub presence: Presence,
+    pub role: Role,
 }
 
 #[derive(serde::Deserialize)]
 pub struct NewRoomPayload {
     pub name: String,
     pub global: bool,
+    #[serde(default)]
+    pub public: bool,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub topic: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct DirectRoomPayload {
+    pub friend_uuid: Uuid,
 }
 
 #[derive(sqlx::FromRow, serde::Serialize)]
@@ -49,31 +85,94 @@ pub fn routes() -> Router {
     Router::new()
         .route("/rooms", get(list_rooms))
         .route("/rooms", post(create_room))
+        .route("/rooms/direct", post(open_direct_room))
         .route("/rooms/{room_id}", get(get_room))
+        .route("/rooms/{room_id}/members", get(list_members))
+        .route("/rooms/{room_id}/members/{user_uuid}", delete(kick_member))
+        .route("/rooms/{room_id}/leave", post(leave_room))
         .route("/rooms/invites", get(list_invites))
         .route("/rooms/invite", post(send_invite))
         .route("/rooms/join", post(accept_request))
         .route("/rooms/decline", post(decline_request))
 }
 
-pub async fn is_member(user_id: i32, room_id: i32, db: &Pool<Postgres>) -> bool {
-    sqlx::query_scalar(
+/// Counts the messages of room `r` that user `$1` has not read, their own excepted
+const UNREAD_COUNT: &str = r#"
+    (
+        SELECT count(*)
+        FROM message_ msg
+        WHERE msg.room = r.id
+        AND msg.deleted_at IS NULL
+        AND msg.sender <> $1
+        AND msg.id > COALESCE(
+            (SELECT c.message FROM read_cursor_ c WHERE c.user_id = $1 AND c.room = r.id),
+            0
+        )
+    )
+"#;
+
+/// Name of room `r` as shown to user `$1`, direct rooms being named after the other user
+pub const ROOM_NAME: &str = r#"
+    CASE WHEN r.direct THEN COALESCE(
+        (
+            SELECT du.username
+            FROM direct_room_ d
+            JOIN user_ du ON du.id = CASE WHEN d.user_first = $1 THEN d.user_second ELSE d.user_first END
+            WHERE d.room = r.id
+        ),
+        r.name
+    ) ELSE r.name END
+"#;
+
+pub const MAX_NAME_LENGTH: usize = 100;
+pub const MAX_DESCRIPTION_LENGTH: usize = 1000;
+pub const MAX_TOPIC_LENGTH: usize = 250;
+
+pub fn check_length(what: &str, value: &str, max: usize) -> Result<(), (StatusCode, String)> {
+    if value.chars().count() > max {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("{what} are limited to {max} characters"),
+        ));
+    }
+    Ok(())
+}
+
+/// Trims a room name, which cannot be blank.
+pub fn validate_name(name: &str) -> Result<String, (StatusCode, String)> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "Room names cannot be empty".into()));
+    }
+    check_length("Room names", name, MAX_NAME_LENGTH)?;
+    Ok(name.to_string())
+}
+
+/// Ids and uuids of every room the user can read, global rooms included.
+pub async fn member_rooms(
+    user_id: i32,
+    db: &Pool<Postgres>,
+) -> Result<Vec<(i32, Uuid)>, (StatusCode, String)> {
+    sqlx::query_as(
         r#"
-        SELECT r.global OR EXISTS (
+        SELECT r.id, r.uuid
+        FROM room_ r
+        WHERE r.global OR EXISTS (
             SELECT 1
             FROM membership_ m
-            WHERE m.user_id = $1
-            AND m.room = r.id
+            WHERE m.user_id = $1 AND m.room = r.id
         )
-        FROM room_ r
-        WHERE r.id = $2
         "#,
     )
     .bind(user_id)
-    .bind(room_id)
-    .fetch_one(db)
+    .fetch_all(db)
     .await
-    .unwrap_or(false)
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Could not list rooms".into(),
+        )
+    })
 }
 
 async fn list_rooms(
@@ -81,19 +180,23 @@ async fn list_rooms(
     Extension(db): Extension<PgPool>,
 ) -> Result<Json<Vec<Room>>, (StatusCode, String)> {
     let claims = verify_jwt(headers)?;
-    if claims.sub != claims.sub {
-        return Err((StatusCode::FORBIDDEN, "Forbidden".to_string()));
-    }
 
     let user_id = user_id_from_uuid(&db, claims.sub).await?;
 
     let rooms = sqlx::query_as::<_, Room>(
-        r#"
+        &r#"
         SELECT r.uuid,
                u.username AS owner_name,
                u.uuid AS owner_uuid,
-               r.name,
-               r.global
+               $NAME AS name,
+               r.description,
+               r.topic,
+               r.avatar,
+               r.global,
+               r.public,
+               r.direct,
+               $UNREAD AS unread,
+               $ROLE AS role
         FROM room_ r
         JOIN user_ u ON u.id = r.owner
         WHERE r.global OR EXISTS (
@@ -101,7 +204,11 @@ async fn list_rooms(
             FROM membership_ m
             WHERE m.user_id = $1 AND m.room = r.id
         )
-        "#,
+        ORDER BY r.direct, r.id
+        "#
+        .replace("$NAME", ROOM_NAME)
+        .replace("$UNREAD", UNREAD_COUNT)
+        .replace("$ROLE", ROLE),
     )
     .bind(user_id)
     .fetch_all(&db)
@@ -120,19 +227,37 @@ async fn create_room(
 
     let user_id = user_id_from_uuid(&db, claims.sub).await?;
 
+    if payload.global && payload.public {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Rooms cannot be both global and public".into(),
+        ));
+    }
+
+    let name = validate_name(&payload.name)?;
+    check_length(
+        "Room descriptions",
+        &payload.description,
+        MAX_DESCRIPTION_LENGTH,
+    )?;
+    check_length("Room topics", &payload.topic, MAX_TOPIC_LENGTH)?;
+
     let room_uuid = uuid::Uuid::now_v7();
 
     sqlx::query(
-        "INSERT INTO room_ (uuid, owner, name, global)
-        VALUES ($1, $2, $3, $4)",
+        "INSERT INTO room_ (uuid, owner, name, description, topic, global, public)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)",
     )
     .bind(room_uuid)
     .bind(user_id)
-    .bind(&payload.name)
-    .bind(&payload.global)
+    .bind(&name)
+    .bind(&payload.description)
+    .bind(&payload.topic)
+    .bind(payload.global)
+    .bind(payload.public)
     .execute(&db)
     .await
-    .map_err(|_| (StatusCode::BAD_REQUEST, format!("Could not create room")))?;
+    .map_err(|_| (StatusCode::BAD_REQUEST, "Could not create room".into()))?;
 
     let room_id = room_id_from_uuid(&db, room_uuid).await?;
 
@@ -142,13 +267,13 @@ async fn create_room(
         .bind(room_id)
         .execute(&db)
         .await
-        .map_err(|_| (StatusCode::BAD_REQUEST, format!("Could not create room")))?;
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Could not create room".into()))?;
 
     let owner_name = sqlx::query_scalar("SELECT username FROM user_ WHERE id = $1")
         .bind(user_id)
         .fetch_one(&db)
         .await
-        .map_err(|_| (StatusCode::BAD_REQUEST, format!("Could not create room")))?;
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Could not create room".into()))?;
 
     Ok((
         StatusCode::CREATED,
@@ -156,8 +281,15 @@ async fn create_room(
             uuid: room_uuid,
             owner_name,
             owner_uuid: claims.sub,
-            name: payload.name,
+            name,
+            description: payload.description,
+            topic: payload.topic,
+            avatar: None,
             global: payload.global,
+            public: payload.public,
+            direct: false,
+            unread: 0,
+            role: Role::Owner,
         }),
     ))
 }
@@ -172,41 +304,353 @@ async fn get_room(
     let user_id = user_id_from_uuid(&db, claims.sub).await?;
     let room_id = room_id_from_uuid(&db, room_uuid).await?;
 
-    if !is_member(user_id, room_id, &db).await {
-        return Err((
-            StatusCode::FORBIDDEN,
-            String::from("You are not a member of this room"),
-        ));
-    }
+    check_permission(&db, user_id, room_id, Permission::Read).await?;
 
-    let room: Room = sqlx::query_as(
-        r#"
+    let room = room_for(&db, user_id, room_id).await?;
+
+    Ok(Json(room))
+}
+
+/// A room as seen by one user.
+pub async fn room_for(
+    db: &PgPool,
+    user_id: i32,
+    room_id: i32,
+) -> Result<Room, (StatusCode, String)> {
+    sqlx::query_as(
+        &r#"
         SELECT
             r.uuid,
             u.username AS owner_name,
             u.uuid AS owner_uuid,
-            r.name,
-            r.global
+            $NAME AS name,
+            r.description,
+            r.topic,
+            r.avatar,
+            r.global,
+            r.public,
+            r.direct,
+            $UNREAD AS unread,
+            $ROLE AS role
         FROM room_ r
         JOIN user_ u ON u.id = r.owner
-        WHERE r.uuid = $1
-        "#,
+        WHERE r.id = $2
+        "#
+        .replace("$NAME", ROOM_NAME)
+        .replace("$UNREAD", UNREAD_COUNT)
+        .replace("$ROLE", ROLE),
     )
-    .bind(room_uuid)
-    .fetch_one(&db)
+    .bind(user_id)
+    .bind(room_id)
+    .fetch_one(db)
     .await
     .map_err(|e| {
         tracing::error!("{e}");
         (StatusCode::NOT_FOUND, "Room not found".to_string())
+    })
+}
+
+/// Gets the direct message room shared with a friend, creating it on first use.
+async fn open_direct_room(
+    headers: HeaderMap,
+    Extension(db): Extension<PgPool>,
+    Json(payload): Json<DirectRoomPayload>,
+) -> Result<(StatusCode, Json<Room>), (StatusCode, String)> {
+    let claims = verify_jwt(headers)?;
+
+    let user_id = user_id_from_uuid(&db, claims.sub).await?;
+    let friend_id = find_user_by_uuid(&db, payload.friend_uuid).await?;
+
+    let (first, second) = if user_id < friend_id {
+        (user_id, friend_id)
+    } else {
+        (friend_id, user_id)
+    };
+
+    let are_friends: bool = sqlx::query_scalar(
+        "SELECT EXISTS (SELECT 1 FROM friendship_ WHERE user_first = $1 AND user_second = $2)",
+    )
+    .bind(first)
+    .bind(second)
+    .fetch_one(&db)
+    .await
+    .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "DB error".into()))?;
+
+    if !are_friends {
+        return Err((
+            StatusCode::FORBIDDEN,
+            "You can only send direct messages to your friends".into(),
+        ));
+    }
+
+    let existing: Option<i32> = sqlx::query_scalar(
+        "SELECT room FROM direct_room_ WHERE user_first = $1 AND user_second = $2",
+    )
+    .bind(first)
+    .bind(second)
+    .fetch_optional(&db)
+    .await
+    .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "DB error".into()))?;
+
+    if let Some(room_id) = existing {
+        return Ok((StatusCode::OK, Json(room_for(&db, user_id, room_id).await?)));
+    }
+
+    let mut tx = db
+        .begin()
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "DB error".into()))?;
+
+    let room_id: i32 = sqlx::query_scalar(
+        "INSERT INTO room_ (uuid, owner, name, direct)
+        VALUES ($1, $2, '', true) RETURNING id",
+    )
+    .bind(Uuid::now_v7())
+    .bind(user_id)
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(|_| (StatusCode::BAD_REQUEST, "Could not create room".into()))?;
+
+    sqlx::query("INSERT INTO membership_ (user_id, room) VALUES ($1, $3), ($2, $3)")
+        .bind(first)
+        .bind(second)
+        .bind(room_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Could not create room".into()))?;
+
+    let created: Option<i32> = sqlx::query_scalar(
+        "INSERT INTO direct_room_ (user_first, user_second, room) VALUES ($1, $2, $3)
+        ON CONFLICT DO NOTHING RETURNING room",
+    )
+    .bind(first)
+    .bind(second)
+    .bind(room_id)
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(|_| (StatusCode::BAD_REQUEST, "Could not create room".into()))?;
+
+    // The other user opened it at the same time, use theirs
+    let Some(room_id) = created else {
+        drop(tx);
+        let room_id: i32 = sqlx::query_scalar(
+            "SELECT room FROM direct_room_ WHERE user_first = $1 AND user_second = $2",
+        )
+        .bind(first)
+        .bind(second)
+        .fetch_one(&db)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "DB error".into()))?;
+
+        return Ok((StatusCode::OK, Json(room_for(&db, user_id, room_id).await?)));
+    };
+
+    tx.commit().await.map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Could not create room".into(),
+        )
+    })?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(room_for(&db, user_id, room_id).await?),
+    ))
+}
+
+/// Everyone who can read the room, which is every user for global rooms.
+async fn list_members(
+    Path(room_uuid): Path<Uuid>,
+    headers: HeaderMap,
+    Extension(db): Extension<PgPool>,
+) -> Result<Json<Vec<RoomMember>>, (StatusCode, String)> {
+    let claims = verify_jwt(headers)?;
+
+    let user_id = user_id_from_uuid(&db, claims.sub).await?;
+    let room_id = room_id_from_uuid(&db, room_uuid).await?;
+
+    check_permission(&db, user_id, room_id, Permission::Read).await?;
+
+    let members = sqlx::query_as::<_, RoomMember>(&format!(
+        r#"
+        SELECT u.uuid, u.username, {PRESENCE} AS presence,
+            CASE WHEN r.owner = u.id THEN 'owner' ELSE COALESCE(
+                (SELECT m.role FROM membership_ m WHERE m.user_id = u.id AND m.room = r.id),
+                'member'
+            ) END AS role
+        FROM user_ u
+        JOIN room_ r ON r.id = $1
+        WHERE r.global OR EXISTS (
+            SELECT 1
+            FROM membership_ m
+            WHERE m.user_id = u.id AND m.room = r.id
+        )
+        ORDER BY u.username
+        "#
+    ))
+    .bind(room_id)
+    .fetch_all(&db)
+    .await
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Could not list room members".into(),
+        )
     })?;
 
-    Ok(Json(Room {
-        uuid: room_uuid,
-        owner_name: room.owner_name,
-        owner_uuid: room.owner_uuid,
-        name: room.name,
-        global: room.global,
-    }))
+    Ok(Json(members))
+}
+
+/// Rooms whose membership is not managed by hand
+async fn check_membership_managed(db: &PgPool, room_id: i32) -> Result<(), (StatusCode, String)> {
+    let (global, direct): (bool, bool) =
+        sqlx::query_as("SELECT global, direct FROM room_ WHERE id = $1")
+            .bind(room_id)
+            .fetch_one(db)
+            .await
+            .map_err(|_| (StatusCode::NOT_FOUND, "Failed to find room".into()))?;
+
+    if global {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Everyone is a member of global rooms".into(),
+        ));
+    }
+
+    if direct {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Direct messages always keep both users".into(),
+        ));
+    }
+
+    Ok(())
+}
+
+async fn remove_member(
+    db: &PgPool,
+    realtime: &Realtime,
+    room_uuid: Uuid,
+    room_id: i32,
+    user_id: i32,
+    kicked: bool,
+) -> Result<(), (StatusCode, String)> {
+    let removed = sqlx::query("DELETE FROM membership_ WHERE user_id = $1 AND room = $2")
+        .bind(user_id)
+        .bind(room_id)
+        .execute(db)
+        .await
+        .map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Could not update room membership".into(),
+            )
+        })?
+        .rows_affected();
+
+    if removed == 0 {
+        return Err((
+            StatusCode::NOT_FOUND,
+            "This user is not a member of this room".into(),
+        ));
+    }
+
+    // Their receipts would keep showing to the remaining members
+    sqlx::query("DELETE FROM read_cursor_ WHERE user_id = $1 AND room = $2")
+        .bind(user_id)
+        .bind(room_id)
+        .execute(db)
+        .await
+        .map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Could not update room membership".into(),
+            )
+        })?;
+
+    let (user_uuid, user): (Uuid, String) =
+        sqlx::query_as("SELECT uuid, username FROM user_ WHERE id = $1")
+            .bind(user_id)
+            .fetch_one(db)
+            .await
+            .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "DB error".into()))?;
+
+    realtime.publish(
+        room_id,
+        Event::MemberLeft {
+            room: room_uuid,
+            user,
+            user_uuid,
+            kicked,
+        },
+    );
+
+    Ok(())
+}
+
+async fn leave_room(
+    Path(room_uuid): Path<Uuid>,
+    headers: HeaderMap,
+    Extension(db): Extension<PgPool>,
+    Extension(realtime): Extension<Realtime>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let claims = verify_jwt(headers)?;
+
+    let user_id = user_id_from_uuid(&db, claims.sub).await?;
+    let room_id = room_id_from_uuid(&db, room_uuid).await?;
+
+    check_membership_managed(&db, room_id).await?;
+
+    if room_role(&db, user_id, room_id).await? == Some(Role::Owner) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "The owner cannot leave their room".into(),
+        ));
+    }
+
+    remove_member(&db, &realtime, room_uuid, room_id, user_id, false).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Removes someone ranked below the caller from a room.
+async fn kick_member(
+    Path((room_uuid, member_uuid)): Path<(Uuid, Uuid)>,
+    headers: HeaderMap,
+    Extension(db): Extension<PgPool>,
+    Extension(realtime): Extension<Realtime>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let claims = verify_jwt(headers)?;
+
+    let user_id = user_id_from_uuid(&db, claims.sub).await?;
+    let room_id = room_id_from_uuid(&db, room_uuid).await?;
+    let member_id = find_user_by_uuid(&db, member_uuid).await?;
+
+    let role = check_permission(&db, user_id, room_id, Permission::Kick).await?;
+    check_membership_managed(&db, room_id).await?;
+
+    if member_id == user_id {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "You cannot kick yourself, leave the room instead".into(),
+        ));
+    }
+
+    let member_role = room_role(&db, member_id, room_id).await?.ok_or((
+        StatusCode::NOT_FOUND,
+        "This user is not a member of this room".into(),
+    ))?;
+
+    if member_role >= role {
+        return Err((
+            StatusCode::FORBIDDEN,
+            "You can only kick members ranked below you".into(),
+        ));
+    }
+
+    remove_member(&db, &realtime, room_uuid, room_id, member_id, true).await?;
+
+    Ok(StatusCode::NO_CONTENT)
 }
 
 async fn list_invites(
@@ -254,6 +698,8 @@ async fn send_invite(
     let receiver_id = id_from_username(&db, payload.receiver_username).await?;
     let room_id = room_id_from_uuid(&db, payload.room_uuid).await?;
 
+    check_permission(&db, sender_id, room_id, Permission::Invite).await?;
+
     if sender_id == receiver_id {
         return Err((
             StatusCode::BAD_REQUEST,
@@ -261,6 +707,19 @@ async fn send_invite(
         ));
     }
 
+    let direct: bool = sqlx::query_scalar("SELECT direct FROM room_ WHERE id = $1")
+        .bind(room_id)
+        .fetch_one(&db)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Database error".into()))?;
+
+    if direct {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Nobody can be invited into direct messages".into(),
+        ));
+    }
+
     let is_already_member = sqlx::query_scalar::<_, bool>(
         r#"
         SELECT EXISTS (
@@ -314,12 +773,14 @@ async fn send_invite(
 async fn accept_request(
     headers: HeaderMap,
     Extension(db): Extension<PgPool>,
+    Extension(realtime): Extension<Realtime>,
     Json(payload): Json<AcceptRoomInvitePayload>,
 ) -> Result<(StatusCode, Json<Room>), (StatusCode, String)> {
     let claims = verify_jwt(headers)?;
 
     let receiver_id = user_id_from_uuid(&db, claims.sub).await?;
     let sender_id = user_id_from_uuid(&db, payload.sender_uuid).await?;
+    let room_id = room_id_from_uuid(&db, payload.room_uuid).await?;
 
     let mut tx = db
         .begin()
@@ -329,11 +790,12 @@ async fn accept_request(
     let rows = sqlx::query(
         r#"
         DELETE FROM room_invite_
-        WHERE sender = $1 AND receiver = $2
+        WHERE sender = $1 AND receiver = $2 AND room = $3
         "#,
     )
     .bind(sender_id)
     .bind(receiver_id)
+    .bind(room_id)
     .execute(&mut *tx)
     .await
     .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "DB error".into()))?
@@ -343,7 +805,14 @@ async fn accept_request(
         return Err((StatusCode::NOT_FOUND, "No such invite".into()));
     }
 
-    let room_id = room_id_from_uuid(&db, payload.room_uuid).await?;
+    // The sender may have left or been demoted since
+    let sender_role = room_role(&db, sender_id, room_id).await?;
+    if !sender_role.is_some_and(|role| role.can(Permission::Invite)) {
+        return Err((
+            StatusCode::FORBIDDEN,
+            "This invite is no longer valid".into(),
+        ));
+    }
 
     sqlx::query("INSERT INTO membership_ (user_id, room) VALUES ($1, $2)")
         .bind(receiver_id)
@@ -357,30 +826,6 @@ async fn accept_request(
             )
         })?;
 
-    let room: Room = sqlx::query_as(
-        r#"
-        SELECT
-            r.uuid,
-            u.username AS owner_name,
-            u.uuid AS owner_uuid,
-            r.name,
-            r.global
-        FROM room_ r
-        JOIN user_ u ON u.id = r.owner
-        WHERE r.id = $1 AND r.owner = $2
-        "#,
-    )
-    .bind(room_id)
-    .bind(sender_id)
-    .fetch_one(&db)
-    .await
-    .map_err(|_| {
-        (
-            StatusCode::NOT_FOUND,
-            "Room not found or wrong owner".into(),
-        )
-    })?;
-
     tx.commit().await.map_err(|_| {
         (
             StatusCode::INTERNAL_SERVER_ERROR,
@@ -388,15 +833,17 @@ async fn accept_request(
         )
     })?;
 
+    realtime.publish(
+        room_id,
+        Event::MemberJoined {
+            room: payload.room_uuid,
+            user: username_from_id(&db, receiver_id).await?,
+        },
+    );
+
     Ok((
         StatusCode::CREATED,
-        Json(Room {
-            uuid: payload.room_uuid,
-            owner_name: room.owner_name,
-            owner_uuid: room.owner_uuid,
-            name: room.name,
-            global: room.global,
-        }),
+        Json(room_for(&db, receiver_id, room_id).await?),
     ))
 }
 
@@ -0,0 +1,142 @@
+use axum::{
+    Extension, Json, Router,
+    extract::Query,
+    http::{HeaderMap, StatusCode},
+    routing::get,
+};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{auth::verify_jwt, db::user_id_from_uuid, routes::messages::TIMESTAMP_FORMAT};
+
+/// A message matching a search
+#[derive(serde::Serialize)]
+pub struct SearchResult {
+    pub uuid: Uuid,
+    pub room: Uuid,
+    pub sender: String,
+    pub sent_at: String,
+    /// HTML-escaped excerpt with the matches wrapped in `<mark>`
+    pub snippet: String,
+}
+
+#[derive(sqlx::FromRow)]
+struct SearchRow {
+    uuid: Uuid,
+    room: Uuid,
+    sender: String,
+    sent_at: chrono::NaiveDateTime,
+    snippet: String,
+}
+
+#[derive(serde::Deserialize)]
+struct SearchQuery {
+    /// Search terms, quotes, `or` and `-` work like on web search engines
+    q: String,
+    room: Option<Uuid>,
+    /// Username of the sender
+    sender: Option<String>,
+    /// First day included
+    from: Option<chrono::NaiveDate>,
+    /// Last day included
+    to: Option<chrono::NaiveDate>,
+    limit: Option<i32>,
+    /// Last result of the previous page
+    before: Option<Uuid>,
+}
+
+pub fn routes() -> Router {
+    Router::new().route("/messages/search", get(search_messages))
+}
+
+/// Searches the messages of every room the caller can read, newest first.
+async fn search_messages(
+    Query(query): Query<SearchQuery>,
+    headers: HeaderMap,
+    Extension(db): Extension<PgPool>,
+) -> Result<Json<Vec<SearchResult>>, (StatusCode, String)> {
+    let claims = verify_jwt(headers)?;
+
+    let user_id = user_id_from_uuid(&db, claims.sub).await?;
+
+    if query.q.trim().is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "Search terms are required".into()));
+    }
+
+    let limit: i32 = query.limit.unwrap_or(30).clamp(1, 80);
+
+    let cursor: Option<i64> = match query.before {
+        Some(before) => Some(
+            sqlx::query_scalar("SELECT id FROM message_ WHERE uuid = $1")
+                .bind(before)
+                .fetch_optional(&db)
+                .await
+                .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "DB error".into()))?
+                .ok_or((StatusCode::NOT_FOUND, "Message not found".into()))?,
+        ),
+        None => None,
+    };
+
+    let results = sqlx::query_as::<_, SearchRow>(
+        r#"
+        SELECT
+            m.uuid,
+            r.uuid AS room,
+            u.username AS sender,
+            m.sent_at,
+            ts_headline(
+                'simple',
+                replace(replace(replace(m.content, '&', '&amp;'), '<', '&lt;'), '>', '&gt;'),
+                query,
+                'StartSel=<mark>, StopSel=</mark>, MinWords=10, MaxWords=30'
+            ) AS snippet
+        FROM message_ m
+        JOIN room_ r ON r.id = m.room
+        JOIN user_ u ON u.id = m.sender
+        CROSS JOIN websearch_to_tsquery('simple', $2) query
+        WHERE m.search_vector @@ query
+        AND m.deleted_at IS NULL
+        AND (r.global OR EXISTS (
+            SELECT 1
+            FROM membership_ ms
+            WHERE ms.user_id = $1 AND ms.room = r.id
+        ))
+        AND ($3::uuid IS NULL OR r.uuid = $3)
+        AND ($4::text IS NULL OR u.username = $4)
+        AND ($5::date IS NULL OR m.sent_at >= $5)
+        AND ($6::date IS NULL OR m.sent_at < $6 + 1)
+        AND ($7::bigint IS NULL OR m.id < $7)
+        ORDER BY m.id DESC
+        LIMIT $8
+        "#,
+    )
+    .bind(user_id)
+    .bind(&query.q)
+    .bind(query.room)
+    .bind(&query.sender)
+    .bind(query.from)
+    .bind(query.to)
+    .bind(cursor)
+    .bind(limit)
+    .fetch_all(&db)
+    .await
+    .map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to search messages: {e}"),
+        )
+    })?;
+
+    Ok(Json(
+        results
+            .into_iter()
+            .map(|r| SearchResult {
+                uuid: r.uuid,
+                room: r.room,
+                sender: r.sender,
+                sent_at: r.sent_at.format(TIMESTAMP_FORMAT).to_string(),
+                snippet: r.snippet,
+            })
+            .collect(),
+    ))
+}
@@ -0,0 +1,147 @@
+use axum::{
+    Extension, Json, Router,
+    extract::Path,
+    http::{HeaderMap, StatusCode},
+    routing::{delete, get},
+};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    auth::verify_jwt, db::user_id_from_uuid, routes::messages::TIMESTAMP_FORMAT,
+    sessions::revoke_session,
+};
+
+/// A device signed into the caller's account
+#[derive(serde::Serialize)]
+pub struct Session {
+    pub uuid: Uuid,
+    pub device: String,
+    pub ip: String,
+    pub created_at: String,
+    pub last_seen: String,
+    /// Whether this is the session making the request
+    pub current: bool,
+}
+
+#[derive(sqlx::FromRow)]
+struct SessionRow {
+    id: Uuid,
+    device: String,
+    ip: String,
+    created_at: chrono::NaiveDateTime,
+    last_seen: chrono::NaiveDateTime,
+}
+
+pub fn routes() -> Router {
+    Router::new()
+        .route(
+            "/sessions",
+            get(list_sessions).delete(revoke_other_sessions),
+        )
+        .route("/sessions/{session_id}", delete(revoke_one_session))
+}
+
+/// The caller's sessions still in use, the most recently seen first.
+async fn list_sessions(
+    headers: HeaderMap,
+    Extension(db): Extension<PgPool>,
+) -> Result<Json<Vec<Session>>, (StatusCode, String)> {
+    let claims = verify_jwt(headers)?;
+
+    let user_id = user_id_from_uuid(&db, claims.sub).await?;
+
+    let sessions = sqlx::query_as::<_, SessionRow>(
+        r#"
+        SELECT id, device, ip, created_at, last_seen
+        FROM session_
+        WHERE user_id = $1 AND revoked_at IS NULL AND expires_at > now()
+        ORDER BY last_seen DESC
+        "#,
+    )
+    .bind(user_id)
+    .fetch_all(&db)
+    .await
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Could not list sessions".into(),
+        )
+    })?;
+
+    Ok(Json(
+        sessions
+            .into_iter()
+            .map(|s| Session {
+                uuid: s.id,
+                device: s.device,
+                ip: s.ip,
+                created_at: s.created_at.format(TIMESTAMP_FORMAT).to_string(),
+                last_seen: s.last_seen.format(TIMESTAMP_FORMAT).to_string(),
+                current: s.id == claims.sid,
+            })
+            .collect(),
+    ))
+}
+
+/// Signs out one of the caller's devices, which may be the current one.
+async fn revoke_one_session(
+    Path(session_id): Path<Uuid>,
+    headers: HeaderMap,
+    Extension(db): Extension<PgPool>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let claims = verify_jwt(headers)?;
+
+    let user_id = user_id_from_uuid(&db, claims.sub).await?;
+
+    let owned: bool = sqlx::query_scalar(
+        r#"
+        SELECT EXISTS (
+            SELECT 1 FROM session_
+            WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL AND expires_at > now()
+        )
+        "#,
+    )
+    .bind(session_id)
+    .bind(user_id)
+    .fetch_one(&db)
+    .await
+    .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "DB error".into()))?;
+
+    if !owned {
+        return Err((StatusCode::NOT_FOUND, "Session not found".into()));
+    }
+
+    revoke_session(&db, session_id).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Signs out every device but the caller's.
+async fn revoke_other_sessions(
+    headers: HeaderMap,
+    Extension(db): Extension<PgPool>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let claims = verify_jwt(headers)?;
+
+    let user_id = user_id_from_uuid(&db, claims.sub).await?;
+
+    sqlx::query(
+        r#"
+        UPDATE session_ SET revoked_at = now()
+        WHERE user_id = $1 AND id <> $2 AND revoked_at IS NULL
+        "#,
+    )
+    .bind(user_id)
+    .bind(claims.sid)
+    .execute(&db)
+    .await
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Could not revoke sessions".into(),
+        )
+    })?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
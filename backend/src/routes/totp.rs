@@ -0,0 +1,223 @@
+use axum::{
+    Extension, Json, Router,
+    http::{HeaderMap, StatusCode},
+    routing::post,
+};
+use sqlx::PgPool;
+
+use crate::{
+    auth::verify_jwt,
+    db::{user_id_from_uuid, username_from_id},
+    totp::{
+        Enrollment, check_code, enrollment, generate_recovery_codes, generate_secret,
+        hash_recovery_code,
+    },
+};
+
+#[derive(serde::Deserialize)]
+pub struct TotpCodePayload {
+    /// Code from the authenticator app, or a recovery code where accepted
+    pub code: String,
+}
+
+/// Shown once, only their hashes are kept
+#[derive(serde::Serialize)]
+pub struct RecoveryCodes {
+    pub recovery_codes: Vec<String>,
+}
+
+pub fn routes() -> Router {
+    Router::new()
+        .route("/account/totp", post(start_enrollment).delete(disable_totp))
+        .route("/account/totp/verify", post(verify_enrollment))
+}
+
+/// Starts setting up two-factor authentication, which only takes effect once a
+/// code is verified. Starting again replaces the pending secret.
+async fn start_enrollment(
+    headers: HeaderMap,
+    Extension(db): Extension<PgPool>,
+) -> Result<(StatusCode, Json<Enrollment>), (StatusCode, String)> {
+    let claims = verify_jwt(headers)?;
+
+    let user_id = user_id_from_uuid(&db, claims.sub).await?;
+
+    let secret = generate_secret();
+
+    let started = sqlx::query(
+        r#"
+        INSERT INTO totp_ (user_id, secret)
+        VALUES ($1, $2)
+        ON CONFLICT (user_id) DO UPDATE
+            SET secret = EXCLUDED.secret, last_step = NULL
+            WHERE totp_.enabled_at IS NULL
+        "#,
+    )
+    .bind(user_id)
+    .bind(&secret)
+    .execute(&db)
+    .await
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Could not start two-factor authentication setup".into(),
+        )
+    })?
+    .rows_affected();
+
+    if started == 0 {
+        return Err((
+            StatusCode::CONFLICT,
+            "Two-factor authentication is already enabled".into(),
+        ));
+    }
+
+    let username = username_from_id(&db, user_id).await?;
+
+    Ok((StatusCode::CREATED, Json(enrollment(secret, &username)?)))
+}
+
+/// Enables two-factor authentication with a first code from the app, returning
+/// the recovery codes.
+async fn verify_enrollment(
+    headers: HeaderMap,
+    Extension(db): Extension<PgPool>,
+    Json(payload): Json<TotpCodePayload>,
+) -> Result<Json<RecoveryCodes>, (StatusCode, String)> {
+    let claims = verify_jwt(headers)?;
+
+    let user_id = user_id_from_uuid(&db, claims.sub).await?;
+
+    let mut tx = db
+        .begin()
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "DB error".into()))?;
+
+    let enabled: bool = sqlx::query_scalar(
+        "SELECT enabled_at IS NOT NULL FROM totp_ WHERE user_id = $1 FOR UPDATE",
+    )
+    .bind(user_id)
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "DB error".into()))?
+    .ok_or((
+        StatusCode::NOT_FOUND,
+        "Two-factor authentication setup was not started".into(),
+    ))?;
+
+    if enabled {
+        return Err((
+            StatusCode::CONFLICT,
+            "Two-factor authentication is already enabled".into(),
+        ));
+    }
+
+    if !check_code(&mut tx, user_id, &payload.code).await? {
+        // Keeps the failure counted
+        tx.commit()
+            .await
+            .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "DB error".into()))?;
+
+        return Err((StatusCode::BAD_REQUEST, "Invalid code".into()));
+    }
+
+    sqlx::query("UPDATE totp_ SET enabled_at = now() WHERE user_id = $1")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "DB error".into()))?;
+
+    let recovery_codes = generate_recovery_codes();
+    let hashes: Vec<String> = recovery_codes
+        .iter()
+        .map(|c| hash_recovery_code(c))
+        .collect();
+
+    sqlx::query(
+        r#"
+        INSERT INTO recovery_code_ (user_id, code_hash)
+        SELECT $1, hash FROM UNNEST($2::text[]) AS hash
+        "#,
+    )
+    .bind(user_id)
+    .bind(&hashes)
+    .execute(&mut *tx)
+    .await
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Could not store recovery codes".into(),
+        )
+    })?;
+
+    tx.commit().await.map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Could not enable two-factor authentication".into(),
+        )
+    })?;
+
+    Ok(Json(RecoveryCodes { recovery_codes }))
+}
+
+/// Turns two-factor authentication off, or cancels its setup. Takes a code or a
+/// recovery code, so a stolen access token alone is not enough.
+async fn disable_totp(
+    headers: HeaderMap,
+    Extension(db): Extension<PgPool>,
+    Json(payload): Json<TotpCodePayload>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let claims = verify_jwt(headers)?;
+
+    let user_id = user_id_from_uuid(&db, claims.sub).await?;
+
+    let mut tx = db
+        .begin()
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "DB error".into()))?;
+
+    if !check_code(&mut tx, user_id, &payload.code).await? {
+        // Nothing to check the code against
+        let enrolled: bool =
+            sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM totp_ WHERE user_id = $1)")
+                .bind(user_id)
+                .fetch_one(&mut *tx)
+                .await
+                .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "DB error".into()))?;
+
+        // Keeps the failure counted
+        tx.commit()
+            .await
+            .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "DB error".into()))?;
+
+        return Err(if enrolled {
+            (StatusCode::BAD_REQUEST, "Invalid code".into())
+        } else {
+            (
+                StatusCode::NOT_FOUND,
+                "Two-factor authentication is not enabled".into(),
+            )
+        });
+    }
+
+    sqlx::query("DELETE FROM recovery_code_ WHERE user_id = $1")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "DB error".into()))?;
+
+    sqlx::query("DELETE FROM totp_ WHERE user_id = $1")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "DB error".into()))?;
+
+    tx.commit().await.map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Could not disable two-factor authentication".into(),
+        )
+    })?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
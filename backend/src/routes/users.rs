@@ -1,21 +1,32 @@
 use axum::{
     Extension, Json, Router,
-    extract::Request,
+    extract::{ConnectInfo, Request},
     http::{HeaderMap, StatusCode},
     middleware::Next,
     response::Response,
     routing::{get, post, put},
 };
 use sqlx::PgPool;
-use std::env;
+use std::{env, net::SocketAddr};
 use uuid::Uuid;
 use validator::ValidateEmail;
 
 use crate::{
-    auth::{create_jwt, hash_password, validate_token, verify_jwt, verify_password},
+    auth::{
+        generate_token, hash_password, hash_token, validate_token, verify_jwt, verify_password,
+    },
     db::{user_id_from_uuid, username_from_uuid},
+    routes::mentions::is_valid_username,
+    sessions::{Tokens, device_name, open_session, refresh_session, revoke_session},
+    totp::check_code,
 };
 
+/// Time left to enter the second factor after the password
+const LOGIN_CHALLENGE_MINUTES: i32 = 5;
+
+/// Wrong codes allowed per login challenge, the password is needed again after that
+const MAX_CHALLENGE_ATTEMPTS: i32 = 5;
+
 const DUMMY_HASH: &str = "$argon2id$v=19$m=4096,t=3,p=1$YWFhYWFhYWFhYWFhYWFhYQ$aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa";
 
 #[derive(sqlx::FromRow, serde::Serialize)]
@@ -30,14 +41,43 @@ pub struct User {
 pub struct LoginPayload {
     pub email: String,
     pub password: String,
+    /// Name of the session, defaults to the user agent
+    #[serde(default)]
+    pub device: Option<String>,
 }
 
+/// Either tokens, or a challenge to pass to `/login/totp` with a code when the
+/// account has two-factor authentication
 #[derive(serde::Serialize)]
 pub struct LoginResponse {
     pub uuid: Uuid,
     pub username: String,
     pub email: String,
-    pub token: String,
+    #[serde(flatten)]
+    pub tokens: Option<Tokens>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub challenge: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct TotpLoginPayload {
+    pub challenge: String,
+    /// Code from the authenticator app, or a recovery code
+    pub code: String,
+}
+
+#[derive(sqlx::FromRow)]
+struct LoginChallengeRow {
+    user_id: i32,
+    uuid: Uuid,
+    username: String,
+    email: String,
+    device: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct RefreshPayload {
+    pub refresh_token: String,
 }
 
 #[derive(serde::Deserialize)]
@@ -45,6 +85,9 @@ pub struct NewUserPayload {
     pub email: String,
     pub username: String,
     pub password: String,
+    /// Name of the session, defaults to the user agent
+    #[serde(default)]
+    pub device: Option<String>,
 }
 
 #[derive(serde::Deserialize)]
@@ -52,18 +95,29 @@ pub struct UpdateUserPayoad {
     pub email: String,
     pub username: String,
     pub password: String,
+    /// Required to change the password or email, not the username alone
+    #[serde(default)]
+    pub current_password: String,
+    /// Required along with it when two-factor authentication is enabled
+    pub code: Option<String>,
 }
 
 #[derive(serde::Serialize)]
 pub struct UpdateUserResponse {
     pub email: String,
     pub username: String,
+    /// Changing the password signs out every session, the caller gets a new one
+    #[serde(flatten)]
+    pub tokens: Option<Tokens>,
 }
 
 pub fn routes() -> Router {
     Router::new()
         .route("/login", post(login))
+        .route("/login/totp", post(login_totp))
         .route("/register", post(register_user))
+        .route("/refresh", post(refresh))
+        .route("/logout", post(logout))
         .route("/validate-token", get(validate_token))
         .route("/account", put(update_user))
         .layer(axum::middleware::from_fn(registration_guard))
@@ -71,7 +125,7 @@ pub fn routes() -> Router {
 
 async fn registration_guard(req: Request, next: Next) -> Result<Response, StatusCode> {
     if req.uri().path() == "/register"
-        && env::var("CHATAPP_PROHIBIT_REGISTRATION").map_or(false, |v| v.to_lowercase() == "true")
+        && env::var("CHATAPP_PROHIBIT_REGISTRATION").is_ok_and(|v| v.to_lowercase() == "true")
     {
         return Err(StatusCode::FORBIDDEN);
     }
@@ -79,6 +133,8 @@ async fn registration_guard(req: Request, next: Next) -> Result<Response, Status
 }
 
 pub async fn login(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
     Extension(db): Extension<PgPool>,
     Json(payload): Json<LoginPayload>,
 ) -> Result<Json<LoginResponse>, (StatusCode, String)> {
@@ -101,18 +157,170 @@ pub async fn login(
         return Err((StatusCode::UNAUTHORIZED, "Invalid credentials".into()));
     }
 
-    let token = create_jwt(user_uuid).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
     let username = username_from_uuid(&db, user_uuid).await?;
+    let device = device_name(payload.device, &headers);
+
+    let totp_enabled: bool = sqlx::query_scalar(
+        r#"
+        SELECT EXISTS (
+            SELECT 1 FROM totp_ t JOIN user_ u ON u.id = t.user_id
+            WHERE u.uuid = $1 AND t.enabled_at IS NOT NULL
+        )
+        "#,
+    )
+    .bind(user_uuid)
+    .fetch_one(&db)
+    .await
+    .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "DB error".into()))?;
+
+    if totp_enabled {
+        let challenge = generate_token();
+
+        sqlx::query(
+            r#"
+            INSERT INTO login_challenge_ (token_hash, user_id, device, expires_at)
+            SELECT $1, id, $3, now() + make_interval(mins => $4)
+            FROM user_ WHERE uuid = $2
+            "#,
+        )
+        .bind(hash_token(&challenge))
+        .bind(user_uuid)
+        .bind(&device)
+        .bind(LOGIN_CHALLENGE_MINUTES)
+        .execute(&db)
+        .await
+        .map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Could not start login".into(),
+            )
+        })?;
+
+        return Ok(Json(LoginResponse {
+            uuid: user_uuid,
+            username,
+            email: payload.email,
+            tokens: None,
+            challenge: Some(challenge),
+        }));
+    }
+
+    let tokens = open_session(&db, user_uuid, &device, addr.ip()).await?;
 
     Ok(Json(LoginResponse {
         uuid: user_uuid,
         username,
         email: payload.email,
-        token,
+        tokens: Some(tokens),
+        challenge: None,
+    }))
+}
+
+/// Second step of logging into an account with two-factor authentication.
+pub async fn login_totp(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Extension(db): Extension<PgPool>,
+    Json(payload): Json<TotpLoginPayload>,
+) -> Result<Json<LoginResponse>, (StatusCode, String)> {
+    let challenge_hash = hash_token(&payload.challenge);
+
+    let mut tx = db
+        .begin()
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "DB error".into()))?;
+
+    let challenge = sqlx::query_as::<_, LoginChallengeRow>(
+        r#"
+        SELECT c.user_id, u.uuid, u.username, u.email, c.device
+        FROM login_challenge_ c
+        JOIN user_ u ON u.id = c.user_id
+        WHERE c.token_hash = $1 AND c.expires_at > now()
+        FOR UPDATE OF c
+        "#,
+    )
+    .bind(&challenge_hash)
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "DB error".into()))?
+    .ok_or((
+        StatusCode::UNAUTHORIZED,
+        "Login expired, enter your password again".into(),
+    ))?;
+
+    if !check_code(&mut tx, challenge.user_id, &payload.code).await? {
+        let attempts: i32 = sqlx::query_scalar(
+            "UPDATE login_challenge_ SET attempts = attempts + 1 WHERE token_hash = $1 RETURNING attempts",
+        )
+        .bind(&challenge_hash)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "DB error".into()))?;
+
+        if attempts >= MAX_CHALLENGE_ATTEMPTS {
+            sqlx::query("DELETE FROM login_challenge_ WHERE token_hash = $1")
+                .bind(&challenge_hash)
+                .execute(&mut *tx)
+                .await
+                .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "DB error".into()))?;
+        }
+
+        tx.commit()
+            .await
+            .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "DB error".into()))?;
+
+        return Err((StatusCode::UNAUTHORIZED, "Invalid code".into()));
+    }
+
+    sqlx::query("DELETE FROM login_challenge_ WHERE token_hash = $1")
+        .bind(&challenge_hash)
+        .execute(&mut *tx)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "DB error".into()))?;
+
+    tx.commit().await.map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Could not complete login".into(),
+        )
+    })?;
+
+    let tokens = open_session(&db, challenge.uuid, &challenge.device, addr.ip()).await?;
+
+    Ok(Json(LoginResponse {
+        uuid: challenge.uuid,
+        username: challenge.username,
+        email: challenge.email,
+        tokens: Some(tokens),
+        challenge: None,
     }))
 }
 
+/// Rotates a refresh token, the one sent cannot be used again.
+pub async fn refresh(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Extension(db): Extension<PgPool>,
+    Json(payload): Json<RefreshPayload>,
+) -> Result<Json<Tokens>, (StatusCode, String)> {
+    Ok(Json(
+        refresh_session(&db, &payload.refresh_token, addr.ip()).await?,
+    ))
+}
+
+/// Ends the caller's session, its access and refresh tokens stop working right away.
+pub async fn logout(
+    headers: HeaderMap,
+    Extension(db): Extension<PgPool>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let claims = verify_jwt(headers)?;
+
+    revoke_session(&db, claims.sid).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
 pub async fn register_user(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
     Extension(db): Extension<PgPool>,
     Json(payload): Json<NewUserPayload>,
 ) -> Result<(StatusCode, Json<LoginResponse>), (StatusCode, String)> {
@@ -127,6 +335,14 @@ pub async fn register_user(
         return Err((StatusCode::BAD_REQUEST, "Invalid email format".into()));
     }
 
+    if !is_valid_username(&payload.username) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Usernames may only contain letters, digits, `_`, `-` and `.`, and cannot end with `.`"
+                .into(),
+        ));
+    }
+
     if payload.password.len() < 8 {
         return Err((
             StatusCode::BAD_REQUEST,
@@ -154,18 +370,19 @@ pub async fn register_user(
     .execute(&db)
     .await
     .map_err(|e| {
-        if let Some(db_err) = e.as_database_error() {
-            if db_err.code().map(|c| c == "23505").unwrap_or(false) {
-                return (
-                    StatusCode::CONFLICT,
-                    "Email or username already taken".into(),
-                );
-            }
+        if let Some(db_err) = e.as_database_error()
+            && db_err.code().map(|c| c == "23505").unwrap_or(false)
+        {
+            return (
+                StatusCode::CONFLICT,
+                "Email or username already taken".into(),
+            );
         }
         (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
     })?;
 
-    let token = create_jwt(user_uuid).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    let device = device_name(payload.device, &headers);
+    let tokens = open_session(&db, user_uuid, &device, addr.ip()).await?;
 
     Ok((
         StatusCode::CREATED,
@@ -173,12 +390,14 @@ pub async fn register_user(
             uuid: user_uuid,
             username: payload.username,
             email: payload.email,
-            token,
+            tokens: Some(tokens),
+            challenge: None,
         }),
     ))
 }
 
 pub async fn update_user(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
     headers: HeaderMap,
     Extension(db): Extension<PgPool>,
     Json(payload): Json<UpdateUserPayoad>,
@@ -196,6 +415,14 @@ pub async fn update_user(
         return Err((StatusCode::BAD_REQUEST, "Invalid email format".into()));
     }
 
+    if !is_valid_username(&payload.username) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Usernames may only contain letters, digits, `_`, `-` and `.`, and cannot end with `.`"
+                .into(),
+        ));
+    }
+
     let user_id = user_id_from_uuid(&db, claims.sub).await?;
 
     let mut tx = db
@@ -203,14 +430,55 @@ pub async fn update_user(
         .await
         .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "DB error".into()))?;
 
-    if !payload.password.is_empty() {
-        if payload.password.len() < 8 {
+    let password_changed = !payload.password.is_empty();
+
+    if password_changed && payload.password.len() < 8 {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Password must be at least 8 characters long".into(),
+        ));
+    }
+
+    let (password_hash, email, totp_enabled): (String, String, bool) = sqlx::query_as(
+        r#"
+        SELECT u.password_hash, u.email, t.enabled_at IS NOT NULL
+        FROM user_ u
+        LEFT JOIN totp_ t ON t.user_id = u.id
+        WHERE u.id = $1
+        "#,
+    )
+    .bind(user_id)
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "DB error".into()))?;
+
+    // Either is enough to take the account over, a stolen access token must not be
+    if password_changed || email != payload.email {
+        if !verify_password(&password_hash, &payload.current_password) {
             return Err((
-                StatusCode::BAD_REQUEST,
-                "Password must be at least 8 characters long".into(),
+                StatusCode::FORBIDDEN,
+                "Current password is incorrect".into(),
             ));
         }
 
+        if totp_enabled {
+            let code = payload.code.as_deref().ok_or((
+                StatusCode::FORBIDDEN,
+                "A two-factor code is required to change the password or email".into(),
+            ))?;
+
+            if !check_code(&mut tx, user_id, code).await? {
+                // Keeps the failure counted
+                tx.commit()
+                    .await
+                    .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "DB error".into()))?;
+
+                return Err((StatusCode::FORBIDDEN, "Invalid code".into()));
+            }
+        }
+    }
+
+    if password_changed {
         let password_hash = hash_password(&payload.password).map_err(|_| {
             (
                 StatusCode::INTERNAL_SERVER_ERROR,
@@ -224,11 +492,24 @@ pub async fn update_user(
             .execute(&mut *tx)
             .await
             .map_err(|e| {
-                return (
+                (
                     StatusCode::INTERNAL_SERVER_ERROR,
                     format!("Failed to update password: {e}"),
-                );
+                )
             })?;
+
+        sqlx::query(
+            "UPDATE session_ SET revoked_at = now() WHERE user_id = $1 AND revoked_at IS NULL",
+        )
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Could not revoke sessions".into(),
+            )
+        })?;
     }
 
     sqlx::query("UPDATE user_ SET username = $1, email = $2 WHERE id = $3")
@@ -238,13 +519,13 @@ pub async fn update_user(
         .execute(&mut *tx)
         .await
         .map_err(|e| {
-            if let Some(db_err) = e.as_database_error() {
-                if db_err.code().map(|c| c == "23505").unwrap_or(false) {
-                    return (
-                        StatusCode::CONFLICT,
-                        "Email or username already taken".into(),
-                    );
-                }
+            if let Some(db_err) = e.as_database_error()
+                && db_err.code().map(|c| c == "23505").unwrap_or(false)
+            {
+                return (
+                    StatusCode::CONFLICT,
+                    "Email or username already taken".into(),
+                );
             }
             (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
         })?;
@@ -256,11 +537,25 @@ pub async fn update_user(
         )
     })?;
 
+    let tokens = if password_changed {
+        // The new session replaces the caller's, on the same device
+        let device: String = sqlx::query_scalar("SELECT device FROM session_ WHERE id = $1")
+            .bind(claims.sid)
+            .fetch_one(&db)
+            .await
+            .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "DB error".into()))?;
+
+        Some(open_session(&db, claims.sub, &device, addr.ip()).await?)
+    } else {
+        None
+    };
+
     Ok((
         StatusCode::CREATED,
         Json(UpdateUserResponse {
             username: payload.username,
             email: payload.email,
+            tokens,
         }),
     ))
 }
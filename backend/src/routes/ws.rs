@@ -1,3 +1,6 @@
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
 use axum::Json;
 use axum::extract::Query;
 use axum::extract::ws::{Message as WsMessage, WebSocket};
@@ -9,22 +12,110 @@ use axum::{
     http::StatusCode,
     response::IntoResponse,
 };
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
 use uuid::Uuid;
 
-use crate::auth::{create_jwt, verify_jwt};
-use crate::db::user_id_from_uuid;
-use crate::routes::rooms::is_member;
+use crate::auth::{Claims, create_jwt, decode_jwt, verify_jwt};
+use crate::db::{user_id_from_uuid, username_from_id};
+use crate::realtime::{Event, RoomId};
+use crate::routes::messages::{
+    NewMessagePayload, latest_message_uuid, messages_after, persist_message,
+};
+use crate::routes::presence;
+use crate::routes::roles::{Permission, check_permission};
+use crate::routes::rooms::member_rooms;
+use crate::sessions::session_is_live;
 use crate::{db::room_id_from_uuid, realtime::Realtime};
 
+/// Number of rows fetched per query when replaying missed messages
+const REPLAY_BATCH: i32 = 100;
+
+/// How long a `typing` frame lasts unless the client sends it again
+const TYPING_TIMEOUT: Duration = Duration::from_secs(5);
+
 #[derive(sqlx::FromRow, serde::Serialize, Deserialize)]
 pub struct WsAuthQuery {
     pub token: String,
 }
 
+#[derive(Deserialize)]
+pub struct WsConnectQuery {
+    pub token: String,
+    /// Last message the client received, to resume `/ws/rooms/{room_uuid}` from
+    pub last_seen: Option<Uuid>,
+}
+
+/// Frames a client can send over an open socket.
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientFrame {
+    SendMessage {
+        /// Client-chosen id echoed back in the matching `ack` or `error` frame
+        nonce: Option<String>,
+        /// Defaults to the socket's room on `/ws/rooms/{room_uuid}`
+        room: Option<Uuid>,
+        #[serde(flatten)]
+        message: NewMessagePayload,
+    },
+    /// Subscribing again to a room with `last_seen` restarts its feed from there
+    Subscribe {
+        room: Uuid,
+        last_seen: Option<Uuid>,
+    },
+    Unsubscribe {
+        room: Uuid,
+    },
+    /// Resend while the user keeps typing, the state expires after `TYPING_TIMEOUT`
+    Typing {
+        /// Defaults to the socket's room on `/ws/rooms/{room_uuid}`
+        room: Option<Uuid>,
+        typing: bool,
+    },
+}
+
+/// Frames the server sends over an open socket.
+/// Realtime events are sent as-is, tagged with their own `type`.
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerFrame {
+    Ack {
+        nonce: Option<String>,
+        uuid: Uuid,
+        sent_at: String,
+    },
+    Error {
+        nonce: Option<String>,
+        error: String,
+    },
+    Subscribed {
+        room: Uuid,
+    },
+    Unsubscribed {
+        room: Uuid,
+    },
+    /// Sent once missed messages have been replayed, live messages follow
+    Replayed {
+        room: Uuid,
+        count: usize,
+    },
+    /// Follows `replayed`. Only messages are replayed, edits, deletions, reactions,
+    /// read receipts, roles and settings changed meanwhile must be fetched again
+    Resync {
+        room: Uuid,
+    },
+    #[serde(untagged)]
+    Event(Event),
+}
+
 pub fn routes() -> axum::Router {
     axum::Router::new()
+        .route("/ws/issue-token", get(issue_user_ws_token))
         .route("/ws/issue-token/rooms/{room_uuid}", get(issue_ws_token))
+        .route("/ws", get(user_ws_handler))
         .route("/ws/rooms/{room_uuid}", get(ws_handler))
 }
 
@@ -38,12 +129,7 @@ pub async fn issue_ws_token(
     let room_id = room_id_from_uuid(&db, room_uuid).await?;
     let user_id = user_id_from_uuid(&db, claims.sub).await?;
 
-    if !is_member(user_id, room_id, &db).await {
-        return Err((
-            StatusCode::UNAUTHORIZED,
-            String::from("You are not a member of this room"),
-        ));
-    }
+    check_permission(&db, user_id, room_id, Permission::Read).await?;
 
     // tracing::info!(
     //     "recieved token issue request from user {} for room {}",
@@ -51,32 +137,102 @@ pub async fn issue_ws_token(
     //     room_uuid
     // );
 
-    let token = create_jwt(claims.sub).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    let token =
+        create_jwt(claims.sub, claims.sid).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    store_ws_token(&db, &token, Some(room_id)).await?;
+
+    Ok((StatusCode::CREATED, Json(WsAuthQuery { token })))
+}
+
+/// Issues a token for the multiplexed `/ws` socket, which is not tied to a single room.
+pub async fn issue_user_ws_token(
+    Extension(db): Extension<sqlx::PgPool>,
+    headers: HeaderMap,
+) -> Result<(StatusCode, Json<WsAuthQuery>), (StatusCode, String)> {
+    let claims = verify_jwt(headers)?;
+
+    // Make sure the account still exists
+    user_id_from_uuid(&db, claims.sub).await?;
 
+    let token =
+        create_jwt(claims.sub, claims.sid).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    store_ws_token(&db, &token, None).await?;
+
+    Ok((StatusCode::CREATED, Json(WsAuthQuery { token })))
+}
+
+async fn store_ws_token(
+    db: &PgPool,
+    token: &str,
+    room_id: Option<i32>,
+) -> Result<(), (StatusCode, String)> {
     sqlx::query(
         r#"
         insert into ws_token_ (token, room_id, expires_at)
         values ($1, $2, now() + interval '30 seconds')
         "#,
     )
-    .bind(&token)
+    .bind(token)
     .bind(room_id)
-    .execute(&db)
+    .execute(db)
     .await
     .map_err(|_| {
         (
             StatusCode::INTERNAL_SERVER_ERROR,
-            format!("failed to provide ws token"),
+            "failed to provide ws token".into(),
         )
     })?;
 
-    Ok((StatusCode::CREATED, Json(WsAuthQuery { token })))
+    Ok(())
+}
+
+/// Consumes a one-time ws token and returns the id of the user it was issued to, with
+/// the claims of the access token it was issued for, whose session must still be live.
+/// `room_id` must match the room the token was issued for, or be `None` for user tokens.
+async fn redeem_ws_token(
+    db: &PgPool,
+    token: &str,
+    room_id: Option<i32>,
+) -> Result<(i32, Claims), StatusCode> {
+    let valid: Option<bool> = sqlx::query_scalar(
+        r#"
+        delete from ws_token_
+        where token = $1
+          and room_id is not distinct from $2
+          and expires_at > now()
+        returning true
+        "#,
+    )
+    .bind(token)
+    .bind(room_id)
+    .fetch_optional(db)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    if valid.is_none() {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    let claims = decode_jwt(token).map_err(|(status, _)| status)?;
+
+    if !session_is_live(db, claims.sid)
+        .await
+        .map_err(|(status, _)| status)?
+    {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    let user_id = user_id_from_uuid(db, claims.sub)
+        .await
+        .map_err(|(status, _)| status)?;
+
+    Ok((user_id, claims))
 }
 
 async fn ws_handler(
     ws: WebSocketUpgrade,
     Path(room_uuid): Path<Uuid>,
-    Query(query): Query<WsAuthQuery>,
+    Query(query): Query<WsConnectQuery>,
     Extension(realtime): Extension<Realtime>,
     Extension(db): Extension<sqlx::PgPool>,
 ) -> Result<impl IntoResponse, axum::http::StatusCode> {
@@ -86,42 +242,504 @@ async fn ws_handler(
         .await
         .map_err(|_| StatusCode::NOT_FOUND)?;
 
-    let valid: Option<i32> = sqlx::query_scalar(
-        r#"
-        delete from ws_token_
-        where token = $1
-          and room_id = $2
-          and expires_at > now()
-        returning room_id
-        "#,
-    )
-    .bind(query.token)
-    .bind(room_id)
-    .fetch_optional(&db)
-    .await
-    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let (user_id, claims) = redeem_ws_token(&db, &query.token, Some(room_id)).await?;
+    let username = username_from_id(&db, user_id)
+        .await
+        .map_err(|(status, _)| status)?;
 
-    if valid.is_none() {
-        return Err(StatusCode::UNAUTHORIZED);
+    Ok(ws.on_upgrade(move |socket| async move {
+        let mut conn = Connection::new(db, realtime, user_id, &claims, username, Some(room_uuid));
+        conn.subscribe(room_id, room_uuid, query.last_seen);
+        conn.run(socket).await;
+    }))
+}
+
+/// Single socket subscribed to every room the user is a member of, global rooms included,
+/// which also receives the events addressed to the user, such as friends' presence.
+async fn user_ws_handler(
+    ws: WebSocketUpgrade,
+    Query(query): Query<WsAuthQuery>,
+    Extension(realtime): Extension<Realtime>,
+    Extension(db): Extension<sqlx::PgPool>,
+) -> Result<impl IntoResponse, axum::http::StatusCode> {
+    let (user_id, claims) = redeem_ws_token(&db, &query.token, None).await?;
+    let username = username_from_id(&db, user_id)
+        .await
+        .map_err(|(status, _)| status)?;
+
+    let rooms = member_rooms(user_id, &db)
+        .await
+        .map_err(|(status, _)| status)?;
+
+    Ok(ws.on_upgrade(move |socket| async move {
+        let mut conn = Connection::new(db, realtime, user_id, &claims, username, None);
+        conn.follow_user();
+        for (room_id, room_uuid) in rooms {
+            conn.subscribe(room_id, room_uuid, None);
+        }
+        conn.run(socket).await;
+    }))
+}
+
+/// State of one open socket. Each subscribed room gets a task forwarding its
+/// broadcasts into a shared queue, which the socket loop drains.
+struct Connection {
+    db: PgPool,
+    realtime: Realtime,
+    user_id: i32,
+    user_uuid: Uuid,
+    /// Sign-in session the socket was opened from, it closes once the session ends
+    auth_session: Uuid,
+    username: String,
+    default_room: Option<Uuid>,
+    subscriptions: HashMap<Uuid, JoinHandle<()>>,
+    /// Forwards the events addressed to the user, on `/ws` only
+    user_feed: Option<JoinHandle<()>>,
+    /// Rooms the user is typing in, with the task that expires the state
+    typing: HashMap<Uuid, (RoomId, JoinHandle<()>)>,
+    out_tx: mpsc::Sender<ServerFrame>,
+    out_rx: mpsc::Receiver<ServerFrame>,
+}
+
+impl Connection {
+    fn new(
+        db: PgPool,
+        realtime: Realtime,
+        user_id: i32,
+        claims: &Claims,
+        username: String,
+        default_room: Option<Uuid>,
+    ) -> Self {
+        let (out_tx, out_rx) = mpsc::channel(256);
+
+        Self {
+            db,
+            realtime,
+            user_id,
+            user_uuid: claims.sub,
+            auth_session: claims.sid,
+            username,
+            default_room,
+            subscriptions: HashMap::new(),
+            user_feed: None,
+            typing: HashMap::new(),
+            out_tx,
+            out_rx,
+        }
     }
 
-    let sender = realtime.sender_for(room_id);
-    let receiver = sender.subscribe();
+    fn subscribe(&mut self, room_id: RoomId, room_uuid: Uuid, last_seen: Option<Uuid>) {
+        if self.subscriptions.contains_key(&room_uuid) {
+            if last_seen.is_none() {
+                return;
+            }
+            self.unsubscribe(room_uuid);
+        }
 
-    Ok(ws.on_upgrade(move |socket| handle_socket(socket, receiver)))
+        // Subscribe before replaying so nothing posted in between is lost
+        let feed = RoomFeed {
+            db: self.db.clone(),
+            user_id: self.user_id,
+            user_uuid: self.user_uuid,
+            room_id,
+            room_uuid,
+            receiver: self.realtime.subscribe(room_id),
+            last_seen,
+            replayed: HashSet::new(),
+            out: self.out_tx.clone(),
+        };
+
+        let task = tokio::spawn(feed.run());
+        self.subscriptions.insert(room_uuid, task);
+    }
+
+    fn follow_user(&mut self) {
+        let mut receiver = self.realtime.subscribe_user(self.user_id);
+        let out = self.out_tx.clone();
+
+        self.user_feed = Some(tokio::spawn(async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(event) => {
+                        if out.send(ServerFrame::Event(event)).await.is_err() {
+                            break;
+                        }
+                    }
+                    // Nothing to replay, these events are not stored
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        }));
+    }
+
+    fn unsubscribe(&mut self, room_uuid: Uuid) -> bool {
+        match self.subscriptions.remove(&room_uuid) {
+            Some(task) => {
+                task.abort();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Announces that the user is typing in a room, until `stop_typing`
+    /// or `TYPING_TIMEOUT` without a new call.
+    async fn start_typing(&mut self, room_uuid: Uuid) -> Result<(), String> {
+        let room_id = room_id_from_uuid(&self.db, room_uuid)
+            .await
+            .map_err(|(_, error)| error)?;
+
+        check_permission(&self.db, self.user_id, room_id, Permission::Send)
+            .await
+            .map_err(|(_, error)| error)?;
+
+        if let Some((_, expiry)) = self.typing.remove(&room_uuid) {
+            expiry.abort();
+        }
+
+        self.realtime
+            .publish(room_id, self.typing_event(room_uuid, true));
+
+        let realtime = self.realtime.clone();
+        let stopped = self.typing_event(room_uuid, false);
+        let expiry = tokio::spawn(async move {
+            tokio::time::sleep(TYPING_TIMEOUT).await;
+            realtime.publish(room_id, stopped);
+        });
+
+        self.typing.insert(room_uuid, (room_id, expiry));
+        Ok(())
+    }
+
+    fn stop_typing(&mut self, room_uuid: Uuid) {
+        let Some((room_id, expiry)) = self.typing.remove(&room_uuid) else {
+            return;
+        };
+
+        // Already announced by the expiry task
+        if expiry.is_finished() {
+            return;
+        }
+
+        expiry.abort();
+        self.realtime
+            .publish(room_id, self.typing_event(room_uuid, false));
+    }
+
+    fn typing_event(&self, room_uuid: Uuid, typing: bool) -> Event {
+        Event::Typing {
+            room: room_uuid,
+            user: self.username.clone(),
+            user_uuid: self.user_uuid,
+            typing,
+        }
+    }
+
+    async fn run(mut self, mut socket: WebSocket) {
+        let session = presence::open_ws_session(&self.db, &self.realtime, self.user_id).await;
+        let mut heartbeat = tokio::time::interval(presence::HEARTBEAT_INTERVAL);
+
+        loop {
+            tokio::select! {
+                _ = heartbeat.tick() => {
+                    // Signing out of a session also disconnects its sockets
+                    if let Ok(false) = session_is_live(&self.db, self.auth_session).await {
+                        let _ = socket.send(WsMessage::Close(None)).await;
+                        break;
+                    }
+
+                    if let Some(session) = session {
+                        presence::refresh_ws_session(&self.db, session).await;
+                    }
+                }
+                frame = self.out_rx.recv() => {
+                    let Some(frame) = frame else { break };
+
+                    if send_frame(&mut socket, &frame).await.is_err() {
+                        break;
+                    }
+                }
+                incoming = socket.recv() => {
+                    let reply = match incoming {
+                        Some(Ok(WsMessage::Text(text))) => self.handle_frame(&text).await,
+                        Some(Ok(WsMessage::Close(_))) | Some(Err(_)) | None => break,
+                        Some(Ok(_)) => continue,
+                    };
+
+                    if let Some(reply) = reply
+                        && send_frame(&mut socket, &reply).await.is_err()
+                    {
+                        break;
+                    }
+                }
+            }
+        }
+
+        for (_, task) in self.subscriptions.drain() {
+            task.abort();
+        }
+
+        if let Some(task) = self.user_feed.take() {
+            task.abort();
+        }
+
+        let typing: Vec<Uuid> = self.typing.keys().copied().collect();
+        for room_uuid in typing {
+            self.stop_typing(room_uuid);
+        }
+
+        if let Some(session) = session {
+            presence::close_ws_session(&self.db, &self.realtime, self.user_id, session).await;
+        }
+    }
+
+    /// Handles a client frame, returning the reply to send back if any.
+    async fn handle_frame(&mut self, text: &str) -> Option<ServerFrame> {
+        let frame = match serde_json::from_str::<ClientFrame>(text) {
+            Ok(frame) => frame,
+            Err(e) => {
+                return Some(ServerFrame::Error {
+                    nonce: None,
+                    error: format!("Invalid frame: {e}"),
+                });
+            }
+        };
+
+        let reply = match frame {
+            ClientFrame::SendMessage {
+                nonce,
+                room,
+                message,
+            } => {
+                let Some(room_uuid) = room.or(self.default_room) else {
+                    return Some(ServerFrame::Error {
+                        nonce,
+                        error: "Missing room".into(),
+                    });
+                };
+
+                match persist_message(&self.db, &self.realtime, self.user_id, room_uuid, message)
+                    .await
+                {
+                    Ok(message) => {
+                        self.stop_typing(room_uuid);
+                        ServerFrame::Ack {
+                            nonce,
+                            uuid: message.uuid,
+                            sent_at: message.sent_at,
+                        }
+                    }
+                    Err((_, error)) => ServerFrame::Error { nonce, error },
+                }
+            }
+            ClientFrame::Subscribe { room, last_seen } => {
+                let room_id = match room_id_from_uuid(&self.db, room).await {
+                    Ok(room_id) => room_id,
+                    Err((_, error)) => return Some(ServerFrame::Error { nonce: None, error }),
+                };
+
+                if let Err((_, error)) =
+                    check_permission(&self.db, self.user_id, room_id, Permission::Read).await
+                {
+                    return Some(ServerFrame::Error { nonce: None, error });
+                }
+
+                self.subscribe(room_id, room, last_seen);
+                ServerFrame::Subscribed { room }
+            }
+            ClientFrame::Unsubscribe { room } => {
+                if !self.unsubscribe(room) {
+                    return Some(ServerFrame::Error {
+                        nonce: None,
+                        error: "Not subscribed to this room".into(),
+                    });
+                }
+
+                ServerFrame::Unsubscribed { room }
+            }
+            // Typing frames are only answered when they fail
+            ClientFrame::Typing { room, typing } => {
+                let Some(room_uuid) = room.or(self.default_room) else {
+                    return Some(ServerFrame::Error {
+                        nonce: None,
+                        error: "Missing room".into(),
+                    });
+                };
+
+                if !typing {
+                    self.stop_typing(room_uuid);
+                    return None;
+                }
+
+                let error = self.start_typing(room_uuid).await.err()?;
+                ServerFrame::Error { nonce: None, error }
+            }
+        };
+
+        Some(reply)
+    }
+}
+
+/// Forwards one room's broadcasts to a connection. Missed messages are read back
+/// from `message_`, both when resuming from `last_seen` and when the broadcast
+/// receiver lags behind.
+struct RoomFeed {
+    db: PgPool,
+    user_id: i32,
+    user_uuid: Uuid,
+    room_id: RoomId,
+    room_uuid: Uuid,
+    receiver: broadcast::Receiver<Event>,
+    last_seen: Option<Uuid>,
+    /// Messages sent by the last replay, whose live copies must be skipped
+    replayed: HashSet<Uuid>,
+    out: mpsc::Sender<ServerFrame>,
 }
 
-async fn handle_socket(
-    mut socket: WebSocket,
-    mut receiver: tokio::sync::broadcast::Receiver<crate::routes::messages::Message>,
-) {
-    while let Ok(msg) = receiver.recv().await {
-        if socket
-            .send(WsMessage::Text(serde_json::to_string(&msg).unwrap().into()))
+impl RoomFeed {
+    async fn run(mut self) {
+        let started = match self.last_seen {
+            Some(_) => self.replay().await,
+            None => {
+                self.last_seen = latest_message_uuid(&self.db, self.room_id)
+                    .await
+                    .ok()
+                    .flatten();
+                Ok(())
+            }
+        };
+
+        if started.is_err() {
+            return;
+        }
+
+        loop {
+            let sent = match self.receiver.recv().await {
+                // The events lost include messages the user must not miss
+                Ok(Event::Gap) => self.replay().await,
+                Ok(event) => {
+                    // Nobody needs to see their own typing indicator
+                    if let Event::Typing { user_uuid, .. } = &event
+                        && *user_uuid == self.user_uuid
+                    {
+                        continue;
+                    }
+
+                    let ends_feed = match &event {
+                        Event::MemberLeft { user_uuid, .. } => *user_uuid == self.user_uuid,
+                        Event::RoomDeleted { .. } => true,
+                        _ => false,
+                    };
+
+                    if ends_feed {
+                        let _ = self.send(ServerFrame::Event(event)).await;
+                        break;
+                    }
+
+                    if let Event::Message(msg) = &event {
+                        if !self.replayed.is_empty() {
+                            if self.replayed.contains(&msg.uuid) {
+                                continue;
+                            }
+                            self.replayed.clear();
+                        }
+
+                        self.last_seen = Some(msg.uuid);
+                    }
+
+                    self.send(ServerFrame::Event(event)).await
+                }
+                Err(RecvError::Lagged(skipped)) => {
+                    tracing::warn!(
+                        "subscriber lagged {skipped} messages behind in room {}, replaying",
+                        self.room_uuid
+                    );
+                    self.replay().await
+                }
+                Err(RecvError::Closed) => break,
+            };
+
+            if sent.is_err() {
+                break;
+            }
+        }
+    }
+
+    /// Sends every stored message after `last_seen`, then `replayed` and `resync` frames.
+    /// Fails once the connection is gone, or when the user can no longer read the room.
+    async fn replay(&mut self) -> Result<(), ()> {
+        // The departure may have been among the skipped events
+        check_permission(&self.db, self.user_id, self.room_id, Permission::Read)
             .await
-            .is_err()
-        {
-            break;
+            .map_err(|_| ())?;
+
+        self.replayed.clear();
+        let mut count = 0;
+
+        loop {
+            let batch = match messages_after(
+                &self.db,
+                self.user_id,
+                self.room_id,
+                self.last_seen,
+                REPLAY_BATCH,
+            )
+            .await
+            {
+                Ok(batch) => batch,
+                Err((_, error)) => {
+                    // Unknown cursor, carry on live from the latest message
+                    self.last_seen = latest_message_uuid(&self.db, self.room_id)
+                        .await
+                        .ok()
+                        .flatten();
+                    self.send(ServerFrame::Error { nonce: None, error }).await?;
+
+                    return self
+                        .send(ServerFrame::Resync {
+                            room: self.room_uuid,
+                        })
+                        .await;
+                }
+            };
+
+            let done = batch.len() < REPLAY_BATCH as usize;
+
+            for msg in batch {
+                self.last_seen = Some(msg.uuid);
+                self.replayed.insert(msg.uuid);
+                count += 1;
+                self.send(ServerFrame::Event(Event::Message(Box::new(msg))))
+                    .await?;
+            }
+
+            if done {
+                break;
+            }
         }
+
+        self.send(ServerFrame::Replayed {
+            room: self.room_uuid,
+            count,
+        })
+        .await?;
+
+        self.send(ServerFrame::Resync {
+            room: self.room_uuid,
+        })
+        .await
     }
+
+    async fn send(&self, frame: ServerFrame) -> Result<(), ()> {
+        self.out.send(frame).await.map_err(|_| ())
+    }
+}
+
+async fn send_frame(socket: &mut WebSocket, frame: &ServerFrame) -> Result<(), axum::Error> {
+    socket
+        .send(WsMessage::Text(
+            serde_json::to_string(frame).unwrap().into(),
+        ))
+        .await
 }
@@ -0,0 +1,362 @@
+use std::net::IpAddr;
+
+use axum::{
+    Extension,
+    extract::Request,
+    http::{
+        HeaderMap, StatusCode,
+        header::{AUTHORIZATION, USER_AGENT},
+    },
+    middleware::Next,
+    response::Response,
+};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::auth::{ACCESS_TOKEN_LIFETIME, create_jwt, decode_jwt, generate_token, hash_token};
+
+/// Sessions unused for this long expire, each refresh extends them
+const SESSION_LIFETIME_DAYS: i32 = 30;
+
+const MAX_DEVICE_LENGTH: usize = 100;
+
+/// A fresh pair of tokens, the refresh token can be used only once
+#[derive(serde::Serialize)]
+pub struct Tokens {
+    /// Access token, sent as `Authorization: Bearer`
+    pub token: String,
+    pub refresh_token: String,
+    /// Lifetime of the access token, in seconds
+    pub expires_in: i64,
+}
+
+#[derive(sqlx::FromRow)]
+struct RefreshTokenRow {
+    session: Uuid,
+    user_uuid: Uuid,
+    used: bool,
+    live: bool,
+}
+
+/// What presenting a refresh token leads to
+#[derive(Debug, PartialEq, Eq)]
+enum Refresh {
+    /// The token is traded for a new pair
+    Rotate,
+    /// The session is over, nothing to do
+    Refuse,
+    /// The token was already traded, so someone else holds it
+    RevokeSession,
+}
+
+impl RefreshTokenRow {
+    fn refresh(&self) -> Refresh {
+        match (self.live, self.used) {
+            (false, _) => Refresh::Refuse,
+            (true, true) => Refresh::RevokeSession,
+            (true, false) => Refresh::Rotate,
+        }
+    }
+}
+
+/// Name shown for a session, as given by the client or else its user agent.
+pub fn device_name(requested: Option<String>, headers: &HeaderMap) -> String {
+    let name = requested
+        .filter(|name| !name.trim().is_empty())
+        .or_else(|| {
+            headers
+                .get(USER_AGENT)
+                .and_then(|v| v.to_str().ok())
+                .map(String::from)
+        })
+        .unwrap_or_else(|| "Unknown device".into());
+
+    name.trim().chars().take(MAX_DEVICE_LENGTH).collect()
+}
+
+async fn issue_tokens(
+    tx: &mut sqlx::PgConnection,
+    user_uuid: Uuid,
+    session: Uuid,
+) -> Result<Tokens, (StatusCode, String)> {
+    let refresh_token = generate_token();
+
+    sqlx::query("INSERT INTO refresh_token_ (token_hash, session) VALUES ($1, $2)")
+        .bind(hash_token(&refresh_token))
+        .bind(session)
+        .execute(tx)
+        .await
+        .map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Could not issue refresh token".into(),
+            )
+        })?;
+
+    let token =
+        create_jwt(user_uuid, session).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+
+    Ok(Tokens {
+        token,
+        refresh_token,
+        expires_in: ACCESS_TOKEN_LIFETIME.num_seconds(),
+    })
+}
+
+/// Signs a user in, starting a new session.
+pub async fn open_session(
+    db: &PgPool,
+    user_uuid: Uuid,
+    device: &str,
+    ip: IpAddr,
+) -> Result<Tokens, (StatusCode, String)> {
+    let mut tx = db
+        .begin()
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "DB error".into()))?;
+
+    let session = Uuid::now_v7();
+
+    sqlx::query(
+        r#"
+        INSERT INTO session_ (id, user_id, device, ip, expires_at)
+        SELECT $1, id, $3, $4, now() + make_interval(days => $5)
+        FROM user_ WHERE uuid = $2
+        "#,
+    )
+    .bind(session)
+    .bind(user_uuid)
+    .bind(device)
+    .bind(ip.to_string())
+    .bind(SESSION_LIFETIME_DAYS)
+    .execute(&mut *tx)
+    .await
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Could not open session".into(),
+        )
+    })?;
+
+    let tokens = issue_tokens(&mut tx, user_uuid, session).await?;
+
+    tx.commit().await.map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Could not open session".into(),
+        )
+    })?;
+
+    Ok(tokens)
+}
+
+/// Exchanges a refresh token for a new pair. A refresh token used twice was
+/// most likely stolen, so its whole session is revoked.
+pub async fn refresh_session(
+    db: &PgPool,
+    refresh_token: &str,
+    ip: IpAddr,
+) -> Result<Tokens, (StatusCode, String)> {
+    let mut tx = db
+        .begin()
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "DB error".into()))?;
+
+    let row = sqlx::query_as::<_, RefreshTokenRow>(
+        r#"
+        SELECT
+            t.session,
+            u.uuid AS user_uuid,
+            t.used_at IS NOT NULL AS used,
+            s.revoked_at IS NULL AND s.expires_at > now() AS live
+        FROM refresh_token_ t
+        JOIN session_ s ON s.id = t.session
+        JOIN user_ u ON u.id = s.user_id
+        WHERE t.token_hash = $1
+        FOR UPDATE OF t, s
+        "#,
+    )
+    .bind(hash_token(refresh_token))
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "DB error".into()))?
+    .ok_or((StatusCode::UNAUTHORIZED, "Invalid refresh token".into()))?;
+
+    match row.refresh() {
+        Refresh::Rotate => {}
+        Refresh::Refuse => {
+            return Err((
+                StatusCode::UNAUTHORIZED,
+                "Session expired or revoked".into(),
+            ));
+        }
+        Refresh::RevokeSession => {
+            sqlx::query("UPDATE session_ SET revoked_at = now() WHERE id = $1")
+                .bind(row.session)
+                .execute(&mut *tx)
+                .await
+                .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "DB error".into()))?;
+
+            tx.commit()
+                .await
+                .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "DB error".into()))?;
+
+            tracing::warn!("refresh token reused, revoked session {}", row.session);
+
+            return Err((
+                StatusCode::UNAUTHORIZED,
+                "Refresh token already used, the session was revoked".into(),
+            ));
+        }
+    }
+
+    sqlx::query("UPDATE refresh_token_ SET used_at = now() WHERE token_hash = $1")
+        .bind(hash_token(refresh_token))
+        .execute(&mut *tx)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "DB error".into()))?;
+
+    sqlx::query(
+        r#"
+        UPDATE session_
+        SET expires_at = now() + make_interval(days => $2), ip = $3, last_seen = now()
+        WHERE id = $1
+        "#,
+    )
+    .bind(row.session)
+    .bind(SESSION_LIFETIME_DAYS)
+    .bind(ip.to_string())
+    .execute(&mut *tx)
+    .await
+    .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "DB error".into()))?;
+
+    let tokens = issue_tokens(&mut tx, row.user_uuid, row.session).await?;
+
+    tx.commit().await.map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Could not refresh session".into(),
+        )
+    })?;
+
+    Ok(tokens)
+}
+
+pub async fn revoke_session(db: &PgPool, session: Uuid) -> Result<(), (StatusCode, String)> {
+    sqlx::query("UPDATE session_ SET revoked_at = now() WHERE id = $1 AND revoked_at IS NULL")
+        .bind(session)
+        .execute(db)
+        .await
+        .map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Could not revoke session".into(),
+            )
+        })?;
+
+    Ok(())
+}
+
+/// Whether a session can still be used, neither revoked nor expired.
+pub async fn session_is_live(db: &PgPool, session: Uuid) -> Result<bool, (StatusCode, String)> {
+    sqlx::query_scalar(
+        r#"
+        SELECT EXISTS (
+            SELECT 1 FROM session_
+            WHERE id = $1 AND revoked_at IS NULL AND expires_at > now()
+        )
+        "#,
+    )
+    .bind(session)
+    .fetch_one(db)
+    .await
+    .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "DB error".into()))
+}
+
+/// Rejects access tokens whose session was revoked or has expired, so signing out
+/// takes effect right away rather than when the token expires. Requests without a
+/// valid token are left for the handlers to refuse. Also keeps `last_seen` current,
+/// to the minute to spare writes.
+pub async fn require_live_session(
+    Extension(db): Extension<PgPool>,
+    req: Request,
+    next: Next,
+) -> Result<Response, (StatusCode, String)> {
+    let claims = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|s| s.strip_prefix("Bearer "))
+        .and_then(|token| decode_jwt(token).ok());
+
+    if let Some(claims) = claims {
+        let stale: bool = sqlx::query_scalar(
+            r#"
+            SELECT last_seen < now() - interval '1 minute'
+            FROM session_
+            WHERE id = $1 AND revoked_at IS NULL AND expires_at > now()
+            "#,
+        )
+        .bind(claims.sid)
+        .fetch_optional(&db)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "DB error".into()))?
+        .ok_or((StatusCode::UNAUTHORIZED, "Session revoked".into()))?;
+
+        if stale {
+            sqlx::query("UPDATE session_ SET last_seen = now() WHERE id = $1")
+                .bind(claims.sid)
+                .execute(&db)
+                .await
+                .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "DB error".into()))?;
+        }
+    }
+
+    Ok(next.run(req).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(live: bool, used: bool) -> RefreshTokenRow {
+        RefreshTokenRow {
+            session: Uuid::nil(),
+            user_uuid: Uuid::nil(),
+            used,
+            live,
+        }
+    }
+
+    #[test]
+    fn unused_tokens_of_live_sessions_are_rotated() {
+        assert_eq!(row(true, false).refresh(), Refresh::Rotate);
+    }
+
+    #[test]
+    fn reused_tokens_revoke_the_session() {
+        assert_eq!(row(true, true).refresh(), Refresh::RevokeSession);
+    }
+
+    #[test]
+    fn tokens_of_ended_sessions_are_refused() {
+        assert_eq!(row(false, false).refresh(), Refresh::Refuse);
+        assert_eq!(row(false, true).refresh(), Refresh::Refuse);
+    }
+
+    #[test]
+    fn device_name_prefers_the_requested_name() {
+        let mut headers = HeaderMap::new();
+        headers.insert(USER_AGENT, "curl/8.0".parse().unwrap());
+
+        assert_eq!(device_name(Some(" Laptop ".into()), &headers), "Laptop");
+        assert_eq!(device_name(Some("  ".into()), &headers), "curl/8.0");
+        assert_eq!(device_name(None, &HeaderMap::new()), "Unknown device");
+    }
+
+    #[test]
+    fn device_name_is_truncated() {
+        let name = device_name(Some("é".repeat(150)), &HeaderMap::new());
+        assert_eq!(name.chars().count(), MAX_DEVICE_LENGTH);
+    }
+}
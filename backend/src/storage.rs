@@ -0,0 +1,121 @@
+use std::{io::Cursor, path::PathBuf, sync::Arc};
+use uuid::Uuid;
+
+/// Longest side of generated thumbnails, in pixels
+const THUMBNAIL_SIZE: u32 = 256;
+
+/// Largest image we decode for a thumbnail, a small file can claim huge dimensions
+const MAX_IMAGE_SIDE: u32 = 8192;
+const MAX_DECODE_ALLOC: u64 = 256 * 1024 * 1024;
+
+/// Attachment files kept in a local directory, named after their uuid.
+/// Thumbnails sit next to the original as `<uuid>.thumb.png`.
+#[derive(Clone)]
+pub struct Storage {
+    root: Arc<PathBuf>,
+    /// Largest accepted upload, in bytes
+    pub max_size: usize,
+    /// Accepted MIME types, `type/*` matches a whole family
+    allowed_types: Arc<Vec<String>>,
+}
+
+impl Storage {
+    pub async fn new(
+        root: PathBuf,
+        max_size: usize,
+        allowed_types: Vec<String>,
+    ) -> std::io::Result<Self> {
+        tokio::fs::create_dir_all(&root).await?;
+
+        Ok(Self {
+            root: Arc::new(root),
+            max_size,
+            allowed_types: Arc::new(
+                allowed_types
+                    .into_iter()
+                    .map(|t| t.trim().to_ascii_lowercase())
+                    .collect(),
+            ),
+        })
+    }
+
+    pub fn allows(&self, mime_type: &str) -> bool {
+        self.allowed_types
+            .iter()
+            .any(|allowed| match allowed.strip_suffix("/*") {
+                Some(family) => mime_type
+                    .split_once('/')
+                    .is_some_and(|(kind, _)| kind == family),
+                None => allowed == mime_type,
+            })
+    }
+
+    fn path(&self, uuid: Uuid) -> PathBuf {
+        self.root.join(uuid.to_string())
+    }
+
+    fn thumbnail_path(&self, uuid: Uuid) -> PathBuf {
+        self.root.join(format!("{uuid}.thumb.png"))
+    }
+
+    pub async fn save(&self, uuid: Uuid, data: &[u8]) -> std::io::Result<()> {
+        tokio::fs::write(self.path(uuid), data).await
+    }
+
+    pub async fn save_thumbnail(&self, uuid: Uuid, data: &[u8]) -> std::io::Result<()> {
+        tokio::fs::write(self.thumbnail_path(uuid), data).await
+    }
+
+    pub async fn load(&self, uuid: Uuid) -> std::io::Result<Vec<u8>> {
+        tokio::fs::read(self.path(uuid)).await
+    }
+
+    pub async fn load_thumbnail(&self, uuid: Uuid) -> std::io::Result<Vec<u8>> {
+        tokio::fs::read(self.thumbnail_path(uuid)).await
+    }
+
+    /// Removes a file and its thumbnail, ignoring whichever does not exist.
+    pub async fn remove(&self, uuid: Uuid) {
+        let _ = tokio::fs::remove_file(self.path(uuid)).await;
+        let _ = tokio::fs::remove_file(self.thumbnail_path(uuid)).await;
+    }
+}
+
+/// Formats whose decoder is compiled in, `image` knows more MIME types than that.
+fn decodable_format(mime_type: &str) -> Option<image::ImageFormat> {
+    match mime_type {
+        "image/png" => Some(image::ImageFormat::Png),
+        "image/jpeg" => Some(image::ImageFormat::Jpeg),
+        "image/gif" => Some(image::ImageFormat::Gif),
+        "image/webp" => Some(image::ImageFormat::WebP),
+        _ => None,
+    }
+}
+
+/// Whether thumbnails can be made for this MIME type.
+pub fn is_thumbnailable(mime_type: &str) -> bool {
+    decodable_format(mime_type).is_some()
+}
+
+/// Decodes an image and scales it down to a PNG thumbnail.
+/// Returns `None` when the data is not a valid image of that type, or too large to decode.
+pub fn thumbnail(data: &[u8], mime_type: &str) -> Option<Vec<u8>> {
+    let format = decodable_format(mime_type)?;
+
+    let mut limits = image::Limits::default();
+    limits.max_image_width = Some(MAX_IMAGE_SIDE);
+    limits.max_image_height = Some(MAX_IMAGE_SIDE);
+    limits.max_alloc = Some(MAX_DECODE_ALLOC);
+
+    let mut reader = image::ImageReader::with_format(Cursor::new(data), format);
+    reader.limits(limits);
+    let image = reader.decode().ok()?;
+
+    let mut png = Vec::new();
+    image
+        .thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE)
+        .write_to(&mut Cursor::new(&mut png), image::ImageFormat::Png)
+        .ok()?;
+
+    Some(png)
+}
@@ -0,0 +1,282 @@
+use axum::http::StatusCode;
+use data_encoding::BASE32_NOPAD;
+use hmac::{Hmac, Mac};
+use password_hash::rand_core::{OsRng, RngCore};
+use qrcode::{QrCode, render::svg};
+use sha1::Sha1;
+use sqlx::PgConnection;
+
+use crate::auth::hash_token;
+
+const ISSUER: &str = "chatapp";
+
+/// Seconds each code is valid for
+const PERIOD: u64 = 30;
+const DIGITS: u32 = 6;
+
+/// Codes from one period before or after are accepted, to allow for clock drift
+const ALLOWED_DRIFT: u64 = 1;
+
+pub const RECOVERY_CODE_COUNT: usize = 10;
+
+/// Invalid codes in a row, across login attempts, before codes are refused for a while
+const MAX_FAILED_CODES: i32 = 10;
+const LOCKOUT_MINUTES: i32 = 15;
+
+/// What an authenticator app needs to generate codes
+#[derive(serde::Serialize)]
+pub struct Enrollment {
+    /// Base32 secret, for apps that cannot scan the QR code
+    pub secret: String,
+    /// `otpauth://` URI, the payload of the QR code
+    pub uri: String,
+    /// The URI as an SVG QR code
+    pub qr_svg: String,
+}
+
+pub fn generate_secret() -> String {
+    let mut bytes = [0u8; 20];
+    OsRng.fill_bytes(&mut bytes);
+    BASE32_NOPAD.encode(&bytes)
+}
+
+pub fn enrollment(secret: String, account: &str) -> Result<Enrollment, (StatusCode, String)> {
+    let label = percent_encode(&format!("{ISSUER}:{account}"));
+    let uri = format!(
+        "otpauth://totp/{label}?secret={secret}&issuer={ISSUER}&algorithm=SHA1&digits={DIGITS}&period={PERIOD}"
+    );
+
+    let qr_svg = QrCode::new(uri.as_bytes())
+        .map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Could not create QR code".into(),
+            )
+        })?
+        .render::<svg::Color>()
+        .min_dimensions(200, 200)
+        .build();
+
+    Ok(Enrollment {
+        secret,
+        uri,
+        qr_svg,
+    })
+}
+
+/// Escapes everything but unreserved characters, as URI labels may hold any username.
+fn percent_encode(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+/// Code for a time step, as in RFC 6238
+fn code_at(secret: &[u8], step: u64) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("HMAC takes keys of any size");
+    mac.update(&step.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let truncated = u32::from_be_bytes([
+        hash[offset],
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]) & 0x7fff_ffff;
+
+    truncated % 10u32.pow(DIGITS)
+}
+
+/// Time step matching `code`, if any within the allowed drift.
+fn matching_step(secret: &str, code: u32) -> Option<u64> {
+    let secret = BASE32_NOPAD.decode(secret.as_bytes()).ok()?;
+    let now = chrono::Utc::now().timestamp() as u64 / PERIOD;
+
+    (now - ALLOWED_DRIFT..=now + ALLOWED_DRIFT).find(|&step| code_at(&secret, step) == code)
+}
+
+/// Random recovery codes, grouped like `abcd-efgh-ijkl-mnop` to be easier to copy.
+pub fn generate_recovery_codes() -> Vec<String> {
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let mut bytes = [0u8; 10];
+            OsRng.fill_bytes(&mut bytes);
+            let code = BASE32_NOPAD.encode(&bytes).to_lowercase();
+
+            code.as_bytes()
+                .chunks(4)
+                .map(|chunk| std::str::from_utf8(chunk).unwrap())
+                .collect::<Vec<_>>()
+                .join("-")
+        })
+        .collect()
+}
+
+/// Recovery codes are hashed without their dashes and case, so they can be typed loosely
+pub fn hash_recovery_code(code: &str) -> String {
+    let normalized: String = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+
+    hash_token(&normalized)
+}
+
+/// Checks a second factor of a user, either a code from their authenticator app or
+/// one of their recovery codes, which then cannot be used again. Codes are single-use
+/// too: a code is refused once it or a later one was accepted. Pending enrollments
+/// are checked the same way, before they have any recovery codes.
+///
+/// Too many invalid codes in a row lock the user out for a while, callers must commit
+/// even when the code is refused so that the failure counts.
+pub async fn check_code(
+    conn: &mut PgConnection,
+    user_id: i32,
+    code: &str,
+) -> Result<bool, (StatusCode, String)> {
+    let code: String = code.chars().filter(|c| !c.is_whitespace()).collect();
+
+    let Some((secret, last_step, locked)): Option<(String, Option<i64>, bool)> = sqlx::query_as(
+        r#"
+        SELECT secret, last_step, locked_until IS NOT NULL AND locked_until > now()
+        FROM totp_ WHERE user_id = $1
+        FOR UPDATE
+        "#,
+    )
+    .bind(user_id)
+    .fetch_optional(&mut *conn)
+    .await
+    .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "DB error".into()))?
+    else {
+        return Ok(false);
+    };
+
+    if locked {
+        return Err((
+            StatusCode::TOO_MANY_REQUESTS,
+            "Too many invalid codes, try again later".into(),
+        ));
+    }
+
+    let valid = if code.len() != DIGITS as usize || !code.chars().all(|c| c.is_ascii_digit()) {
+        let used = sqlx::query(
+            r#"
+            UPDATE recovery_code_ SET used_at = now()
+            WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL
+            "#,
+        )
+        .bind(user_id)
+        .bind(hash_recovery_code(&code))
+        .execute(&mut *conn)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "DB error".into()))?
+        .rows_affected();
+
+        used > 0
+    } else {
+        match code
+            .parse()
+            .ok()
+            .and_then(|code| matching_step(&secret, code))
+        {
+            Some(step) if last_step.is_none_or(|last| step as i64 > last) => {
+                sqlx::query("UPDATE totp_ SET last_step = $2 WHERE user_id = $1")
+                    .bind(user_id)
+                    .bind(step as i64)
+                    .execute(&mut *conn)
+                    .await
+                    .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "DB error".into()))?;
+
+                true
+            }
+            _ => false,
+        }
+    };
+
+    let counted = if valid {
+        sqlx::query("UPDATE totp_ SET failed_attempts = 0 WHERE user_id = $1")
+            .bind(user_id)
+            .execute(&mut *conn)
+            .await
+    } else {
+        sqlx::query(
+            r#"
+            UPDATE totp_ SET
+                failed_attempts = CASE WHEN failed_attempts + 1 >= $2 THEN 0 ELSE failed_attempts + 1 END,
+                locked_until = CASE
+                    WHEN failed_attempts + 1 >= $2 THEN now() + make_interval(mins => $3)
+                    ELSE locked_until
+                END
+            WHERE user_id = $1
+            "#,
+        )
+        .bind(user_id)
+        .bind(MAX_FAILED_CODES)
+        .bind(LOCKOUT_MINUTES)
+        .execute(&mut *conn)
+        .await
+    };
+
+    counted.map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "DB error".into()))?;
+
+    Ok(valid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RFC_SECRET: &[u8] = b"12345678901234567890";
+
+    /// RFC 6238 SHA-1 test vectors, keeping the last six of their eight digits
+    #[test]
+    fn codes_match_rfc_6238() {
+        for (time, code) in [
+            (59, 287082),
+            (1111111109, 81804),
+            (1111111111, 50471),
+            (1234567890, 5924),
+            (2000000000, 279037),
+            (20000000000, 353130),
+        ] {
+            assert_eq!(code_at(RFC_SECRET, time / PERIOD), code, "T = {time}");
+        }
+    }
+
+    #[test]
+    fn current_code_is_accepted() {
+        let secret = generate_secret();
+        let bytes = BASE32_NOPAD.decode(secret.as_bytes()).unwrap();
+        let now = chrono::Utc::now().timestamp() as u64 / PERIOD;
+
+        assert_eq!(matching_step(&secret, code_at(&bytes, now)), Some(now));
+        assert_eq!(matching_step(&secret, code_at(&bytes, now + 5)), None);
+    }
+
+    #[test]
+    fn labels_are_percent_encoded() {
+        assert_eq!(percent_encode("chatapp:jean luc"), "chatapp%3Ajean%20luc");
+        assert_eq!(percent_encode("a.b_c-d~"), "a.b_c-d~");
+        assert_eq!(percent_encode("zoé"), "zo%C3%A9");
+    }
+
+    #[test]
+    fn recovery_codes_are_hashed_loosely() {
+        let codes = generate_recovery_codes();
+        assert_eq!(codes.len(), RECOVERY_CODE_COUNT);
+
+        let code = &codes[0];
+        assert_eq!(
+            hash_recovery_code(code),
+            hash_recovery_code(&code.to_uppercase().replace('-', " "))
+        );
+        assert_ne!(hash_recovery_code(code), hash_recovery_code(&codes[1]));
+    }
+}
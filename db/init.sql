@@ -3,7 +3,9 @@ CREATE TABLE IF NOT EXISTS user_ (
   uuid UUID UNIQUE,
   email TEXT UNIQUE,
   username TEXT NOT NULL UNIQUE,
-  password_hash TEXT NOT NULL
+  password_hash TEXT NOT NULL,
+  -- Shown while connected, 'offline' hides the user
+  status VARCHAR(16) NOT NULL DEFAULT 'online' CHECK (status IN ('online', 'away', 'offline'))
 );
 
 CREATE TABLE IF NOT EXISTS friendship_ (
@@ -26,37 +28,212 @@ CREATE TABLE IF NOT EXISTS room_ (
   uuid UUID UNIQUE,
   owner INT NOT NULL REFERENCES user_(id),
   name TEXT NOT NULL,
-  global BOOLEAN NOT NULL DEFAULT false
+  description TEXT NOT NULL DEFAULT '',
+  topic TEXT NOT NULL DEFAULT '',
+  -- Stored with the attachments under this uuid
+  avatar UUID,
+  global BOOLEAN NOT NULL DEFAULT false,
+  -- Listed in the directory, anyone can join and leave
+  public BOOLEAN NOT NULL DEFAULT false,
+  -- Direct messages between two friends, which can't be renamed or invited into
+  direct BOOLEAN NOT NULL DEFAULT false,
+  CHECK (global::int + public::int + direct::int <= 1)
 );
 
 CREATE TABLE IF NOT EXISTS membership_ (
-  user_id INT REFERENCES user_(id),
-  room INT REFERENCES room_(id),
+  user_id INT NOT NULL REFERENCES user_(id) ON DELETE CASCADE,
+  room INT NOT NULL REFERENCES room_(id) ON DELETE CASCADE,
+  -- The owner is room_.owner, whatever their role here
+  role VARCHAR(16) NOT NULL DEFAULT 'member' CHECK (role IN ('admin', 'moderator', 'member')),
   PRIMARY KEY (user_id, room)
 );
 
+-- The one direct message room of each pair of users, user_first < user_second
+CREATE TABLE IF NOT EXISTS direct_room_ (
+  user_first INT NOT NULL REFERENCES user_(id) ON DELETE CASCADE,
+  user_second INT NOT NULL REFERENCES user_(id) ON DELETE CASCADE,
+  room INT NOT NULL UNIQUE REFERENCES room_(id) ON DELETE CASCADE,
+  PRIMARY KEY (user_first, user_second),
+  CHECK (user_first < user_second)
+);
+
 CREATE TABLE IF NOT EXISTS room_invite_ (
   sender INT NOT NULL REFERENCES user_(id) ON DELETE CASCADE,
   receiver INT NOT NULL REFERENCES user_(id) ON DELETE CASCADE,
-  room INT NOT NULL,
+  room INT NOT NULL REFERENCES room_(id) ON DELETE CASCADE,
   sent_at TIMESTAMP NOT NULL DEFAULT now(),
   PRIMARY KEY (sender, receiver),
   CHECK (sender <> receiver)
 );
 
+-- Shareable codes joining a room, revoked ones are kept for their history
+CREATE TABLE IF NOT EXISTS invite_link_ (
+  id SERIAL PRIMARY KEY,
+  code VARCHAR(32) NOT NULL UNIQUE,
+  room INT NOT NULL REFERENCES room_(id) ON DELETE CASCADE,
+  creator INT NOT NULL REFERENCES user_(id) ON DELETE CASCADE,
+  created_at TIMESTAMP NOT NULL DEFAULT now(),
+  expires_at TIMESTAMP,
+  max_uses INT CHECK (max_uses > 0),
+  uses INT NOT NULL DEFAULT 0,
+  revoked_at TIMESTAMP,
+  revoked_by INT REFERENCES user_(id) ON DELETE SET NULL
+);
+
+-- Who joined through which link
+CREATE TABLE IF NOT EXISTS invite_link_use_ (
+  id SERIAL PRIMARY KEY,
+  link INT NOT NULL REFERENCES invite_link_(id) ON DELETE CASCADE,
+  user_id INT NOT NULL REFERENCES user_(id) ON DELETE CASCADE,
+  used_at TIMESTAMP NOT NULL DEFAULT now()
+);
+
 CREATE TABLE IF NOT EXISTS message_ (
   id BIGSERIAL PRIMARY KEY,
-  uuid UUID NOT NULL,
+  uuid UUID NOT NULL UNIQUE,
   sender INT REFERENCES user_(id) NOT NULL,
-  room INT REFERENCES room_(id) NOT NULL,
-  message_type VARCHAR(32) NOT NULL,
+  room INT REFERENCES room_(id) ON DELETE CASCADE NOT NULL,
+  message_type VARCHAR(32) NOT NULL CHECK (message_type IN ('text', 'system', 'reply', 'attachment')),
+  content TEXT NOT NULL,
+  sent_at TIMESTAMP NOT NULL DEFAULT now(),
+  edited_at TIMESTAMP,
+  -- Deleted messages are kept as tombstones so pagination cursors stay valid
+  deleted_at TIMESTAMP,
+  deleted_by INT REFERENCES user_(id),
+  -- Message this one replies to, always in the same room
+  parent BIGINT REFERENCES message_(id),
+  -- Only what people wrote is searchable, the 'simple' config works for any language
+  search_vector TSVECTOR GENERATED ALWAYS AS (
+    CASE WHEN message_type IN ('text', 'reply') THEN to_tsvector('simple', content) END
+  ) STORED
+);
+
+CREATE INDEX IF NOT EXISTS message_parent_idx ON message_ (parent) WHERE parent IS NOT NULL;
+CREATE INDEX IF NOT EXISTS message_search_idx ON message_ USING GIN (search_vector);
+
+-- Previous contents of edited messages
+CREATE TABLE IF NOT EXISTS message_revision_ (
+  id BIGSERIAL PRIMARY KEY,
+  message BIGINT NOT NULL REFERENCES message_(id) ON DELETE CASCADE,
   content TEXT NOT NULL,
-  sent_at TIMESTAMP NOT NULL DEFAULT now()
+  replaced_at TIMESTAMP NOT NULL DEFAULT now()
+);
+
+-- Uploaded files, stored on disk under their uuid
+CREATE TABLE IF NOT EXISTS attachment_ (
+  id SERIAL PRIMARY KEY,
+  uuid UUID NOT NULL UNIQUE,
+  room INT NOT NULL REFERENCES room_(id) ON DELETE CASCADE,
+  uploader INT NOT NULL REFERENCES user_(id),
+  filename TEXT NOT NULL,
+  mime_type TEXT NOT NULL,
+  size BIGINT NOT NULL,
+  thumbnail BOOLEAN NOT NULL DEFAULT false,
+  uploaded_at TIMESTAMP NOT NULL DEFAULT now()
+);
+
+CREATE TABLE IF NOT EXISTS reaction_ (
+  message BIGINT NOT NULL REFERENCES message_(id) ON DELETE CASCADE,
+  user_id INT NOT NULL REFERENCES user_(id) ON DELETE CASCADE,
+  emoji VARCHAR(64) NOT NULL,
+  reacted_at TIMESTAMP NOT NULL DEFAULT now(),
+  PRIMARY KEY (message, user_id, emoji)
+);
+
+-- Users mentioned with `@username` in a message, unread until their read cursor passes it
+CREATE TABLE IF NOT EXISTS mention_ (
+  message BIGINT NOT NULL REFERENCES message_(id) ON DELETE CASCADE,
+  user_id INT NOT NULL REFERENCES user_(id) ON DELETE CASCADE,
+  read_at TIMESTAMP,
+  PRIMARY KEY (message, user_id)
 );
 
+CREATE INDEX IF NOT EXISTS mention_unread_idx ON mention_ (user_id) WHERE read_at IS NULL;
+
+-- Last message each user has read in a room
+CREATE TABLE IF NOT EXISTS read_cursor_ (
+  user_id INT NOT NULL REFERENCES user_(id) ON DELETE CASCADE,
+  room INT NOT NULL REFERENCES room_(id) ON DELETE CASCADE,
+  message BIGINT NOT NULL REFERENCES message_(id) ON DELETE CASCADE,
+  read_at TIMESTAMP NOT NULL DEFAULT now(),
+  PRIMARY KEY (user_id, room)
+);
+
+-- Signed-in devices, each holding one valid refresh token at a time
+CREATE TABLE IF NOT EXISTS session_ (
+  id UUID PRIMARY KEY,
+  user_id INT NOT NULL REFERENCES user_(id) ON DELETE CASCADE,
+  device VARCHAR(100) NOT NULL,
+  -- Address of the last sign-in or refresh
+  ip TEXT NOT NULL,
+  created_at TIMESTAMP NOT NULL DEFAULT now(),
+  last_seen TIMESTAMP NOT NULL DEFAULT now(),
+  -- Pushed back on every refresh
+  expires_at TIMESTAMP NOT NULL,
+  revoked_at TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS session_user_idx ON session_ (user_id);
+
+-- Every refresh token issued, kept once used so replays can be detected
+CREATE TABLE IF NOT EXISTS refresh_token_ (
+  token_hash TEXT PRIMARY KEY,
+  session UUID NOT NULL REFERENCES session_(id) ON DELETE CASCADE,
+  issued_at TIMESTAMP NOT NULL DEFAULT now(),
+  used_at TIMESTAMP
+);
+
+-- Authenticator app secrets, only required at login once enabled
+CREATE TABLE IF NOT EXISTS totp_ (
+  user_id INT PRIMARY KEY REFERENCES user_(id) ON DELETE CASCADE,
+  secret TEXT NOT NULL,
+  -- Set once a first code was verified
+  enabled_at TIMESTAMP,
+  -- Time step of the last accepted code, codes up to it cannot be used again
+  last_step BIGINT,
+  -- Invalid codes in a row, too many of them lock codes out until locked_until
+  failed_attempts INT NOT NULL DEFAULT 0,
+  locked_until TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS recovery_code_ (
+  id SERIAL PRIMARY KEY,
+  user_id INT NOT NULL REFERENCES user_(id) ON DELETE CASCADE,
+  code_hash TEXT NOT NULL,
+  used_at TIMESTAMP,
+  UNIQUE (user_id, code_hash)
+);
+
+-- Logins waiting for their second factor
+CREATE TABLE IF NOT EXISTS login_challenge_ (
+  token_hash TEXT PRIMARY KEY,
+  user_id INT NOT NULL REFERENCES user_(id) ON DELETE CASCADE,
+  device VARCHAR(100) NOT NULL,
+  attempts INT NOT NULL DEFAULT 0,
+  expires_at TIMESTAMP NOT NULL
+);
+
+-- Realtime events too large for a NOTIFY payload, fetched by the other instances
+CREATE TABLE IF NOT EXISTS realtime_event_ (
+  id BIGSERIAL PRIMARY KEY,
+  payload TEXT NOT NULL,
+  created_at TIMESTAMP NOT NULL DEFAULT now()
+);
+
+-- Open websocket connections, kept alive by heartbeats
+CREATE TABLE IF NOT EXISTS ws_session_ (
+  id UUID PRIMARY KEY,
+  user_id INT NOT NULL REFERENCES user_(id) ON DELETE CASCADE,
+  connected_at TIMESTAMP NOT NULL DEFAULT now(),
+  last_seen TIMESTAMP NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS ws_session_user_idx ON ws_session_ (user_id);
+
+-- room_id is NULL for tokens that open the multiplexed per-user socket
 CREATE TABLE ws_token_ (
   token TEXT PRIMARY KEY,
-  room_id INT NOT NULL,
+  room_id INT,
   expires_at TIMESTAMPTZ NOT NULL
 );
 
@@ -1,10 +1,51 @@
-import { UpdateUserResponse } from '../types';
+import { Session, TotpEnrollment, UpdateUserResponse } from '../types';
 import { apiFetch } from './client'
 // import type { User } from '../types'
 
-export function updateSettings(username: string, email: string, password: string) {
+// Changing the password or email takes the current password, and a code when two-factor authentication is on
+export function updateSettings(
+  username: string,
+  email: string,
+  password: string,
+  currentPassword: string,
+  code?: string,
+) {
   return apiFetch<UpdateUserResponse>('/settings', {
     method: 'PUT',
-    body: JSON.stringify({ username, email, password }),
+    body: JSON.stringify({ username, email, password, current_password: currentPassword, code }),
+  });
+}
+
+export function logout() {
+  return apiFetch<void>('/logout', { method: 'POST' });
+}
+
+export function fetchSessions() {
+  return apiFetch<Session[]>('/sessions');
+}
+
+export function revokeSession(sessionUuid: string) {
+  return apiFetch<void>(`/sessions/${sessionUuid}`, { method: 'DELETE' });
+}
+
+export function revokeOtherSessions() {
+  return apiFetch<void>('/sessions', { method: 'DELETE' });
+}
+
+export function startTotpEnrollment() {
+  return apiFetch<TotpEnrollment>('/account/totp', { method: 'POST' });
+}
+
+export function verifyTotpEnrollment(code: string) {
+  return apiFetch<{ recovery_codes: string[] }>('/account/totp/verify', {
+    method: 'POST',
+    body: JSON.stringify({ code }),
+  });
+}
+
+export function disableTotp(code: string) {
+  return apiFetch<void>('/account/totp', {
+    method: 'DELETE',
+    body: JSON.stringify({ code }),
   });
 }
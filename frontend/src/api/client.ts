@@ -1,23 +1,58 @@
 import { fetch } from '@tauri-apps/plugin-http';
-import { getAuthData, clearAuthData } from '../authStore'
+import { getAuthData, clearAuthData, saveTokens } from '../authStore'
 import { API } from '../main.ts'
 import router from '../router'
+import type { Tokens } from '../types'
 
-export async function apiFetch<T>(
-  path: string,
-  options: RequestInit = {}
-): Promise<T> {
-  const auth = await getAuthData()
+// Refresh tokens are single-use, concurrent requests share one refresh
+let refreshing: Promise<boolean> | null = null
 
-  const res = await fetch(`${API}${path}`, {
+async function refreshTokens(refreshToken: string): Promise<boolean> {
+  const res = await fetch(`${API}/refresh`, {
+    method: 'POST',
+    headers: { 'Content-Type': 'application/json' },
+    body: JSON.stringify({ refresh_token: refreshToken }),
+  })
+
+  if (!res.ok) return false
+
+  await saveTokens(await res.json() as Tokens)
+  return true
+}
+
+function send(path: string, options: RequestInit, token: string | null) {
+  return fetch(`${API}${path}`, {
     ...options,
     method: options.method || 'GET',
     headers: {
       'Content-Type': 'application/json',
-      ...(auth.token ? { Authorization: `Bearer ${auth.token}` } : {}),
+      ...(token ? { Authorization: `Bearer ${token}` } : {}),
       ...options.headers,
     },
   })
+}
+
+export async function apiFetch<T>(
+  path: string,
+  options: RequestInit = {}
+): Promise<T> {
+  const auth = await getAuthData()
+
+  let res = await send(path, options, auth.token)
+
+  // The access token expired, retry once with a fresh one
+  if (res.status === 401 && auth.token) {
+    const current = await getAuthData()
+
+    if (current.token !== auth.token) {
+      res = await send(path, options, current.token)
+    } else if (current.refreshToken) {
+      refreshing ??= refreshTokens(current.refreshToken).finally(() => { refreshing = null })
+      if (await refreshing) {
+        res = await send(path, options, (await getAuthData()).token)
+      }
+    }
+  }
 
   if (res.status === 401 && auth.token) {
     await clearAuthData()
@@ -1,5 +1,5 @@
 import { apiFetch } from './client'
-import type { Message } from '../types'
+import type { Mention, Message, SearchResult } from '../types'
 
 export function fetchMessages(roomUuid: string, before?: string, limit: number = 30) {
   let url = `/messages/${roomUuid}?limit=${limit}`;
@@ -19,6 +19,33 @@ export function sendMessage(roomUuid: string, content: string) {
   })
 }
 
+export interface SearchFilters {
+  room?: string
+  sender?: string
+  from?: string
+  to?: string
+  before?: string
+  limit?: number
+}
+
+export function searchMessages(q: string, filters: SearchFilters = {}) {
+  const params = new URLSearchParams({ q });
+  for (const [key, value] of Object.entries(filters)) {
+    if (value !== undefined && value !== '') {
+      params.set(key, String(value));
+    }
+  }
+  return apiFetch<SearchResult[]>(`/messages/search?${params}`);
+}
+
+export function fetchMentions() {
+  return apiFetch<Mention[]>('/mentions');
+}
+
+export function markRead(roomUuid: string, messageUuid: string) {
+  return apiFetch<void>(`/messages/${roomUuid}/${messageUuid}/read`, { method: 'POST' });
+}
+
 export async function getWsToken(roomUuid: string): Promise<string> {
   const data = await apiFetch<{ token: string }>(`/ws/issue-token/rooms/${roomUuid}`);
   return data.token;
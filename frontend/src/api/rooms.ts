@@ -1,5 +1,5 @@
 import { apiFetch } from './client'
-import type { Room, RoomInvite } from '../types'
+import type { DirectoryRoom, InviteLink, Role, Room, RoomInvite, RoomMember } from '../types'
 
 export function fetchRooms() {
   return apiFetch<Room[]>(`/rooms`)
@@ -9,10 +9,63 @@ export function fetchRoomInfo(uuid: string) {
   return apiFetch<Room>(`/rooms/${uuid}`)
 }
 
-export function createRoom(name: string, global: boolean) {
+export function createRoom(name: string, global: boolean, isPublic = false) {
   return apiFetch<Room>('/rooms', {
     method: 'POST',
-    body: JSON.stringify({ name, global }),
+    body: JSON.stringify({ name, global, public: isPublic }),
+  })
+}
+
+export function fetchDirectory(search = '', offset = 0, limit = 20) {
+  const params = new URLSearchParams({ q: search, offset: String(offset), limit: String(limit) })
+  return apiFetch<DirectoryRoom[]>(`/rooms/directory?${params}`)
+}
+
+export function joinRoom(uuid: string) {
+  return apiFetch<Room>(`/rooms/${uuid}/join`, { method: 'POST' })
+}
+
+export function updateRoom(uuid: string, settings: { name?: string, description?: string, topic?: string, public?: boolean }) {
+  return apiFetch<Room>(`/rooms/${uuid}`, {
+    method: 'PATCH',
+    body: JSON.stringify(settings),
+  })
+}
+
+export function transferRoom(uuid: string, userUuid: string) {
+  return apiFetch<Room>(`/rooms/${uuid}/transfer`, {
+    method: 'POST',
+    body: JSON.stringify({ user_uuid: userUuid }),
+  })
+}
+
+export function deleteRoom(uuid: string) {
+  return apiFetch<void>(`/rooms/${uuid}`, { method: 'DELETE' })
+}
+
+export function openDirectRoom(friendUuid: string) {
+  return apiFetch<Room>('/rooms/direct', {
+    method: 'POST',
+    body: JSON.stringify({ friend_uuid: friendUuid }),
+  })
+}
+
+export function fetchRoomMembers(uuid: string) {
+  return apiFetch<RoomMember[]>(`/rooms/${uuid}/members`)
+}
+
+export function leaveRoom(uuid: string) {
+  return apiFetch<void>(`/rooms/${uuid}/leave`, { method: 'POST' })
+}
+
+export function kickMember(roomUuid: string, userUuid: string) {
+  return apiFetch<void>(`/rooms/${roomUuid}/members/${userUuid}`, { method: 'DELETE' })
+}
+
+export function setMemberRole(roomUuid: string, userUuid: string, role: Role) {
+  return apiFetch<void>(`/rooms/${roomUuid}/members/${userUuid}/role`, {
+    method: 'PUT',
+    body: JSON.stringify({ role }),
   })
 }
 
@@ -40,3 +93,22 @@ export function declineRoomInvite(senderUuid: string, roomUuid: string) {
     body: JSON.stringify({ sender_uuid: senderUuid, room_uuid: roomUuid }),
   })
 }
+
+export function fetchInviteLinks(roomUuid: string) {
+  return apiFetch<InviteLink[]>(`/rooms/${roomUuid}/links`)
+}
+
+export function createInviteLink(roomUuid: string, expiresIn?: number, maxUses?: number) {
+  return apiFetch<InviteLink>(`/rooms/${roomUuid}/links`, {
+    method: 'POST',
+    body: JSON.stringify({ expires_in: expiresIn, max_uses: maxUses }),
+  })
+}
+
+export function revokeInviteLink(roomUuid: string, code: string) {
+  return apiFetch<void>(`/rooms/${roomUuid}/links/${code}`, { method: 'DELETE' })
+}
+
+export function redeemInviteLink(code: string) {
+  return apiFetch<Room>(`/invites/${code}`, { method: 'POST' })
+}
@@ -1,5 +1,5 @@
 import { load, Store } from '@tauri-apps/plugin-store'
-import { UpdateUserResponse, User } from './types'
+import { Tokens, UpdateUserResponse, User } from './types'
 
 let store: Store | null = null
 
@@ -11,17 +11,26 @@ async function getStore() {
 export async function getAuthData() {
   const s = await getStore()
   const token = await s.get<string>('token')
+  const refreshToken = await s.get<string>('refresh_token')
   const user = await s.get<User>('user')
-  return { token: token || null, user: user || null, isAuthenticated: !!token }
+  return { token: token || null, refreshToken: refreshToken || null, user: user || null, isAuthenticated: !!token }
 }
 
-export async function saveAuthData(token: string, user: User) {
+export async function saveAuthData(token: string, refreshToken: string, user: User) {
   const s = await getStore()
   await s.set('token', token)
+  await s.set('refresh_token', refreshToken)
   await s.set('user', user)
   await s.save()
 }
 
+export async function saveTokens(tokens: Tokens) {
+  const s = await getStore()
+  await s.set('token', tokens.token)
+  await s.set('refresh_token', tokens.refresh_token)
+  await s.save()
+}
+
 export async function clearAuthData() {
   const s = await getStore()
   s.clear()
@@ -37,6 +46,11 @@ export async function updateLocalUser(newData: UpdateUserResponse, uuid?: string
 
   const s = await getStore()
   await s.set('user', updatedUser)
+  // A new password signs out every session, this one included
+  if (newData.token && newData.refresh_token) {
+    await s.set('token', newData.token)
+    await s.set('refresh_token', newData.refresh_token)
+  }
   await s.save()
 }
 
@@ -15,6 +15,8 @@ auth-username = Username
 auth-password = Password
 auth-confirm-password = confirm password
 auth-login-btn = Login
+auth-totp-code = Authentication code
+auth-totp-hint = Enter the code from your authenticator app, or a recovery code
 auth-register-btn = Create Account
 auth-no-account = Don't have an account?
 auth-has-account = Already have an account?
@@ -26,12 +28,14 @@ auth-error-email-invalid = Please enter a valid email address
 ## Chat page
 chat-no-room = Select a room to start talking
 chat-input-placeholder = type a message
+chat-typing = {$users} typing…
 chat-invite-title = Invite People
 chat-invite-receiver = Receiver username
 chat-invite-friend-too = Also send a friend request
 chat-invite-send = Send
 chat-invite-username-placeholder = username
 chat-room-list-title = Rooms
+chat-direct-list-title = Direct messages
 chat-room-owner = by {$owner}
 chat-create-title = Create room
 chat-create-name = Room name
@@ -74,6 +78,9 @@ settings-account-update-modal-title = Update your Account
 settings-account-update-subtitle = Only fill in the fields you wish to change.
 settings-new-password = New Password
 settings-new-password-confirm = Confirm new password
+settings-current-password = Current password
+settings-totp-code-if-enabled = Authentication code, if two-factor authentication is on
+settings-error-current-password = Enter your current password to change your password or email.
 settings-update-save = Save Changes
 settings-updating = Updating...
 settings-error-required = Username and Email are required.
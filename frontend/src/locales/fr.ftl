@@ -15,6 +15,8 @@ auth-username = Nom d'utilisateur
 auth-password = Mot de passe
 auth-confirm-password = confirmer le mot de passe
 auth-login-btn = Se connecter
+auth-totp-code = Code d'authentification
+auth-totp-hint = Entrez le code de votre application d'authentification, ou un code de récupération
 auth-register-btn = Créer un compte
 auth-no-account = Pas encore de compte ?
 auth-has-account = Déjà un compte ?
@@ -26,12 +28,14 @@ auth-error-email-invalid = Veuillez entrer une adresse email valide
 ## Chat page
 chat-no-room = Sélectionnez un salon pour commencer à discuter
 chat-input-placeholder = tapez un message
+chat-typing = {$users} écrit…
 chat-invite-title = Inviter des gens
 chat-invite-receiver = Nom du destinataire
 chat-invite-friend-too = Envoyer aussi une demande d'ami
 chat-invite-send = Envoyer
 chat-invite-username-placeholder = nom d'utilisateur
 chat-room-list-title = Salons
+chat-direct-list-title = Messages privés
 chat-room-owner = par {$owner}
 chat-create-title = Créer un salon
 chat-create-name = Nom du salon
@@ -74,6 +78,9 @@ settings-account-update-modal-title = Modifier votre compte
 settings-account-update-subtitle = Remplissez uniquement ce que vous souhaitez changer.
 settings-new-password = Nouveau mot de passe
 settings-new-password-confirm = Confirmer le mot de passe
+settings-current-password = Mot de passe actuel
+settings-totp-code-if-enabled = Code d'authentification, si la double authentification est activée
+settings-error-current-password = Entrez votre mot de passe actuel pour changer de mot de passe ou d'email.
 settings-update-save = Enregistrer
 settings-updating = Mise à jour...
 settings-error-required = Le nom d'utilisateur et l'email sont requis.
@@ -4,6 +4,7 @@ import * as authStore from './authStore'
 import { ref, computed } from 'vue'
 import { fetchFriendRequests } from './api/friends'
 import { fetchRoomInvites } from './api/rooms'
+import { logout as endSession } from './api/account'
 import type { FriendRequest, RoomInvite } from './types'
 
 export const initAuth = authStore.getAuthData
@@ -16,16 +17,38 @@ export async function login(email: string, username: string, password: string) {
     body: JSON.stringify({ email, username, password }),
   })
 
+  return saveLogin(res)
+}
+
+export async function loginTotp(challenge: string, code: string) {
+  const res: LoginResponse = await apiFetch('/login/totp', {
+    method: 'POST',
+    body: JSON.stringify({ challenge, code }),
+  })
+
+  return saveLogin(res)
+}
+
+async function saveLogin(res: LoginResponse) {
+  if (!res.token || !res.refresh_token) {
+    return { token: null, uuid: res.uuid, isAuthenticated: false, challenge: res.challenge ?? null }
+  }
+
   let user: User = {
     uuid: res.uuid,
     username: res.username,
     email: res.email
   };
-  await authStore.saveAuthData(res.token, user)
-  return { token: res.token, uuid: res.uuid, isAuthenticated: true }
+  await authStore.saveAuthData(res.token, res.refresh_token, user)
+  return { token: res.token, uuid: res.uuid, isAuthenticated: true, challenge: null }
 }
 
 export async function logout() {
+  try {
+    await endSession()
+  } catch (e) {
+    // Signed out locally all the same
+  }
   await authStore.clearAuthData()
   return { token: null, uuid: null, isAuthenticated: false }
 }
@@ -4,16 +4,45 @@ export interface User {
   email: string
 }
 
+// Accounts with two-factor authentication get a challenge instead of tokens
 export interface LoginResponse {
   uuid: string
   username: string
   email: string
+  token?: string
+  refresh_token?: string
+  expires_in?: number
+  challenge?: string
+}
+
+export interface TotpEnrollment {
+  secret: string
+  uri: string
+  qr_svg: string
+}
+
+export interface Tokens {
   token: string
+  refresh_token: string
+  expires_in: number
+}
+
+export interface Session {
+  uuid: string
+  device: string
+  ip: string
+  created_at: string
+  last_seen: string
+  current: boolean
 }
 
 export interface UpdateUserResponse {
   username: string
   email: string
+  // Set when the password changed, which signs out every session
+  token?: string
+  refresh_token?: string
+  expires_in?: number
 }
 
 export interface Room {
@@ -21,20 +50,123 @@ export interface Room {
   owner_name: string
   owner_uuid: string
   name: string
+  description: string
+  topic: string
+  avatar: string | null
   global: boolean
+  public: boolean
+  direct: boolean
+  unread: number
+  role: Role
 }
 
+export type Role = 'owner' | 'admin' | 'moderator' | 'member'
+
 export interface Message {
   uuid: string
   sender: string
-  message_type: 'text'
+  room: string
+  message_type: 'text' | 'system' | 'reply' | 'attachment'
   content: string
   sent_at: string
+  edited_at: string | null
+  deleted_at: string | null
+  parent: MessageParent | null
+  attachment: Attachment | null
+  reactions: Reaction[]
+}
+
+export interface SearchResult {
+  uuid: string
+  room: string
+  sender: string
+  sent_at: string
+  snippet: string
+}
+
+export interface Mention {
+  uuid: string
+  room: string
+  room_name: string
+  sender: string
+  content: string
+  sent_at: string
+}
+
+export interface Attachment {
+  uuid: string
+  filename: string
+  mime_type: string
+  size: number
+  thumbnail: boolean
+}
+
+export interface Reaction {
+  emoji: string
+  count: number
+  reacted: boolean
 }
 
+export interface MessageParent {
+  uuid: string
+  sender: string
+  snippet: string
+}
+
+export type RoomEvent =
+  | ({ type: 'message' } & Message)
+  | { type: 'message_edited', room: string, uuid: string, content: string, edited_at: string }
+  | { type: 'message_deleted', room: string, uuid: string, deleted_at: string }
+  | { type: 'reaction_added' | 'reaction_removed', room: string, uuid: string, emoji: string, user: string }
+  | { type: 'messages_read', room: string, user: string, uuid: string, read_at: string }
+  | { type: 'typing', room: string, user: string, user_uuid: string, typing: boolean }
+  | { type: 'member_joined', room: string, user: string }
+  | { type: 'member_left', room: string, user: string, user_uuid: string, kicked: boolean }
+  | { type: 'role_changed', room: string, user: string, role: Role }
+  | { type: 'room_updated', room: string, name: string, description: string, topic: string, avatar: string | null, public: boolean }
+  | { type: 'room_deleted', room: string }
+  | { type: 'replayed', room: string, count: number }
+  | { type: 'resync', room: string }
+
+export type UserEvent =
+  | { type: 'presence', uuid: string, user: string, presence: Presence }
+  | ({ type: 'mentioned' } & Mention)
+  | { type: 'gap' }
+
+export type Presence = 'online' | 'away' | 'offline'
+
 export interface Friend {
   uuid: string
   username: string
+  presence: Presence
+}
+
+export interface RoomMember {
+  uuid: string
+  username: string
+  presence: Presence
+  role: Role
+}
+
+export interface DirectoryRoom {
+  uuid: string
+  name: string
+  description: string
+  topic: string
+  avatar: string | null
+  members: number
+  joined: boolean
+}
+
+export interface InviteLink {
+  code: string
+  room: string
+  creator: string
+  created_at: string
+  expires_at: string | null
+  max_uses: number | null
+  uses: number
+  revoked: boolean
 }
 
 export interface FriendRequest {